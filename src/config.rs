@@ -1,74 +1,357 @@
 extern crate clap;
 
 use std::fmt::Formatter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{env, fmt, process};
 
+use crate::conflict::ConflictPolicy;
+use crate::extensions::{Extensions, SUPPORTED_EXTENSIONS};
+use crate::normalize::{Normalization, DEFAULT_TITLE_CASE_EXCEPTIONS};
+use crate::ordinary_file::FilePattern;
+use crate::path_pattern::PathPattern;
+use crate::report::OutputFormat;
+use crate::sanitize::{self, SanitizeProfile};
+use crate::template::Template;
+use crate::transliterate::Locale;
 use crate::util;
-use clap::{crate_authors, crate_version, App, Arg};
+use crate::util::{LengthUnit, UnicodeNormalization};
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
+use regex::Regex;
+
+const DEFAULT_COMPILATION_DIR_ARTIST: &str = "Various Artists";
+const DEFAULT_FEAT: &str = "feat.";
+const DEFAULT_LIBRARY_PATH: &str = "{albumartist}/{album}";
+const DEFAULT_PATH_PATTERN: &str = "{artist}/{album}/{track} - {title}";
+
+/// What the program is asked to do
+#[derive(Debug, Default, PartialEq)]
+pub enum Command {
+    /// Rename the music files below the start directory
+    #[default]
+    Rename,
+    /// Revert the changes recorded in the given journal
+    Undo(PathBuf),
+    /// Carry out the actions of a plan written with `--output-format json`
+    Apply(PathBuf),
+    /// Write the tags found in the paths of the music files below the start directory
+    TagFromName,
+}
 
 #[derive(Default)]
 pub struct Config {
+    /// The locale names are transliterated to ASCII with, or `None` to keep them as they are
+    pub ascii: Option<Locale>,
+    pub command: Command,
+    pub compilation_dir_artist: String,
+    /// The name the album cover is renamed to, or `None` to keep its name
+    pub cover_name: Option<String>,
+    /// The names of the non-music files `--remove` removes if any keep or delete patterns are given
+    pub delete_patterns: Vec<FilePattern>,
+    pub dir_template: Option<Template>,
     pub dry_run: bool,
+    /// Whether shortened names end with "…"
+    pub ellipsis: bool,
+    pub extensions: Extensions,
+    pub file_template: Option<Template>,
+    /// Whether the user is asked before a directory is changed
+    pub interactive: bool,
+    pub journal: Option<PathBuf>,
+    /// The names of the non-music files `--remove` keeps
+    pub keep_patterns: Vec<FilePattern>,
+    pub length_unit: LengthUnit,
+    pub library_path: Vec<Template>,
+    pub library_root: Option<PathBuf>,
+    /// The maximum length of the files' paths relative to the path root, or 0 for no limit
+    pub max_path_length: usize,
     pub name_length: u32,
+    pub normalization: Normalization,
+    pub on_conflict: ConflictPolicy,
+    pub output_format: OutputFormat,
     pub omit_artist: bool,
+    /// Whether `tag-from-name` replaces tags that are already set
+    pub overwrite_tags: bool,
+    /// Whether the directories left empty are removed
+    pub prune_empty_dirs: bool,
+    /// The directory the path length is measured from instead of the library root or start directory
+    pub path_root: Option<PathBuf>,
+    /// The patterns to infer missing tags from the path with, empty unless asked to
+    pub path_patterns: Vec<PathPattern>,
+    /// The number of days after which trashed files are removed for good
+    pub purge_trash_older_than: Option<u64>,
     pub remove_artist: bool,
     pub remove_ordinary_files: bool,
     pub rename_directory: bool,
+    /// Replacements applied to the names before the sanitization profile's own
+    pub replacements: Vec<(String, String)>,
+    /// The file system the names have to be valid on
+    pub sanitize_profile: SanitizeProfile,
     pub shorten_names: bool,
     pub start_dir: PathBuf,
+    /// The directory removed non-music files are moved to instead of being deleted
+    pub trash_dir: Option<PathBuf>,
+    pub unicode_normalization: UnicodeNormalization,
     pub verbose: bool,
+    /// Whether the normalized tags are written back to the files
+    pub write_tags: bool,
 }
 
 impl Config {
     pub fn new() -> Config {
+        const APPLY: &str = "apply";
         const ARTIST: &str = "artist";
+        const ASCII: &str = "ascii";
+        const ASCII_LOCALE: &str = "ascii-locale";
+        const ASCII_LOCALE_VALUE: &str = "LOCALE";
+        const ARTIST_VALUE: &str = "ARTIST";
+        const COMPILATION_DIR_ARTIST: &str = "compilation-dir-artist";
+        const COVER_NAME: &str = "cover-name";
+        const COVER_NAME_VALUE: &str = "NAME";
+        const DELETE: &str = "delete";
         const DIRECTORY: &str = "directory";
+        const DIR_TEMPLATE: &str = "dir-template";
         const DRY_RUN: &str = "dry-run";
+        const ELLIPSIS: &str = "ellipsis";
+        const EXCLUDE_EXTENSIONS: &str = "exclude-extensions";
+        const EXTENSIONS: &str = "extensions";
+        const EXTENSIONS_VALUE: &str = "EXTENSIONS";
+        const FEAT: &str = "feat";
+        const FEAT_VALUE: &str = "SPELLING";
+        const FILE_TEMPLATE: &str = "file-template";
+        const INFER_FROM_PATH: &str = "infer-from-path";
+        const INTERACTIVE: &str = "interactive";
+        const JOURNAL: &str = "journal";
+        const JOURNAL_VALUE: &str = "JOURNAL";
+        const KEEP: &str = "keep";
+        const FILE_PATTERN_VALUE: &str = "PATTERN";
         const LENGTH: &str = "limit-length";
+        const LENGTH_UNIT: &str = "length-unit";
+        const LENGTH_UNIT_VALUE: &str = "UNIT";
         const LENGTH_VALUE: &str = "LENGTH";
+        const MAX_PATH_LENGTH: &str = "max-path-length";
+        const LIBRARY_PATH: &str = "library-path";
+        const MOVE_TO: &str = "move-to";
+        const MOVE_TO_VALUE: &str = "LIBRARY_ROOT";
+        const TEMPLATE_VALUE: &str = "TEMPLATE";
+        const UNDO: &str = "undo";
+        const NORMALIZE: &str = "normalize";
+        const NORMALIZE_VALUE: &str = "STEPS";
         const OMIT_ARTIST: &str = "omit-artist";
+        const ON_CONFLICT: &str = "on-conflict";
+        const ON_CONFLICT_VALUE: &str = "POLICY";
+        const OUTPUT_FORMAT: &str = "output-format";
+        const OUTPUT_FORMAT_VALUE: &str = "FORMAT";
+        const OVERWRITE: &str = "overwrite";
+        const PATH_PATTERN: &str = "path-pattern";
+        const PATH_PATTERN_VALUE: &str = "PATTERN";
+        const PATH_ROOT: &str = "path-root";
+        const PATH_ROOT_VALUE: &str = "DIR";
+        const PLAN_VALUE: &str = "PLAN";
+        const PRUNE_EMPTY_DIRS: &str = "prune-empty-dirs";
+        const PURGE_TRASH_OLDER_THAN: &str = "purge-trash-older-than";
+        const PURGE_TRASH_OLDER_THAN_VALUE: &str = "DAYS";
         const REMOVE: &str = "remove";
+        const REPLACE: &str = "replace";
+        const REPLACE_VALUE: &str = "FROM=TO";
+        const SANITIZE_PROFILE: &str = "sanitize-profile";
+        const SANITIZE_PROFILE_VALUE: &str = "PROFILE";
         const START_DIR: &str = "START_DIR";
+        const STRIP: &str = "strip";
+        const STRIP_VALUE: &str = "REGEX";
+        const TAG_FROM_NAME: &str = "tag-from-name";
+        const TITLE_CASE_EXCEPTIONS: &str = "title-case-exceptions";
+        const TRASH_DIR: &str = "trash-dir";
+        const TRASH_DIR_VALUE: &str = "DIR";
+        const UNICODE_NORMALIZATION: &str = "unicode-normalization";
+        const UNICODE_NORMALIZATION_VALUE: &str = "FORM";
         const VERBOSE: &str = "verbose";
+        const WORDS_VALUE: &str = "WORDS";
+        const WRITE_TAGS: &str = "write-tags";
 
-        let matches = App::new("mp3rename")
-            // use crate_version! to pull the version number
-            .version(crate_version!())
-            .author(crate_authors!())
-            .about(
-                "Traverses a directory tree and renames all music files and,
+        let default_extensions = SUPPORTED_EXTENSIONS.join(",");
+        let about = format!(
+            "Traverses a directory tree and renames all music files and,
 optionally, the directories containing them according to the
 tags in the music files.
 The resulting file name will have the form
 [<Disc Number> - ]<Track Number> [<Artist> - ]<Track Title>.<extension>
-(with extension in <mp3|flac|m4a|m4b|m4p|m4v>).",
-            )
+(with extension in <{}>)
+unless a file template is given.",
+            Extensions::default()
+        );
+
+        let matches = App::new("mp3rename")
+            // use crate_version! to pull the version number
+            .version(crate_version!())
+            .author(crate_authors!())
+            // the start directory isn't needed for subcommands
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .about(about.as_str())
             .arg(
                 Arg::with_name(ARTIST)
                     .short("a")
                     .long(ARTIST)
                     .help("Removes the artist from the filename if it is the same for all files in a directory"),
             )
+            .arg(
+                Arg::with_name(ASCII)
+                    .long(ASCII)
+                    .help("Transliterates the names to ASCII, e.g. for car stereos showing other characters as \"?\""),
+            )
+            .arg(
+                Arg::with_name(ASCII_LOCALE)
+                    .long(ASCII_LOCALE)
+                    .takes_value(true)
+                    .value_name(ASCII_LOCALE_VALUE)
+                    .possible_values(&["default", "de"])
+                    .default_value("default")
+                    .help("Transliterates the names the way of <LOCALE>; \"de\" writes umlauts as \"ae\", \"oe\", and \"ue\""),
+            )
+            .arg(
+                Arg::with_name(COMPILATION_DIR_ARTIST)
+                    .long(COMPILATION_DIR_ARTIST)
+                    .takes_value(true)
+                    .value_name(ARTIST_VALUE)
+                    .default_value(DEFAULT_COMPILATION_DIR_ARTIST)
+                    .help("Uses <ARTIST> as the album artist {albumartist} in the directory names of compilations"),
+            )
+            .arg(
+                Arg::with_name(COVER_NAME)
+                    .long(COVER_NAME)
+                    .takes_value(true)
+                    .value_name(COVER_NAME_VALUE)
+                    .help("Renames the album cover to <NAME>, e.g. \"cover.jpg\" or \"folder.jpg\", keeping the image's extension"),
+            )
+            .arg(
+                Arg::with_name(DELETE)
+                    .long(DELETE)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name(FILE_PATTERN_VALUE)
+                    .requires(REMOVE)
+                    .help("Only removes the non-music files whose names match <PATTERN>, a glob like \"*.nfo\" or a regular expression like \"re:^scan\\d+\"; other files not matching --keep are reported and left alone; can be given several times"),
+            )
             .arg(
                 Arg::with_name(DIRECTORY)
                     .short("d")
                     .long(DIRECTORY)
                     .help("Renames directories according to the album tag"),
             )
+            .arg(
+                Arg::with_name(DIR_TEMPLATE)
+                    .long(DIR_TEMPLATE)
+                    .takes_value(true)
+                    .value_name(TEMPLATE_VALUE)
+                    .help("Renames directories according to <TEMPLATE> instead of the album tag, e.g. \"{artist} - {album} [{format}]\""),
+            )
             .arg(
                 Arg::with_name(DRY_RUN)
                     .short("n")
                     .long(DRY_RUN)
+                    .global(true)
                     .help("Uses dry-run mode"),
             )
+            .arg(
+                Arg::with_name(ELLIPSIS)
+                    .long(ELLIPSIS)
+                    .help("Ends names shortened with --limit-length with \"…\""),
+            )
+            .arg(
+                Arg::with_name(EXCLUDE_EXTENSIONS)
+                    .long(EXCLUDE_EXTENSIONS)
+                    .takes_value(true)
+                    .value_name(EXTENSIONS_VALUE)
+                    .help("Doesn't treat files with the comma-separated <EXTENSIONS> as music files, e.g. \"m4v\""),
+            )
+            .arg(
+                Arg::with_name(EXTENSIONS)
+                    .long(EXTENSIONS)
+                    .takes_value(true)
+                    .value_name(EXTENSIONS_VALUE)
+                    .default_value(&default_extensions)
+                    .help("Treats files with the comma-separated <EXTENSIONS> as music files"),
+            )
+            .arg(
+                Arg::with_name(FEAT)
+                    .long(FEAT)
+                    .takes_value(true)
+                    .value_name(FEAT_VALUE)
+                    .default_value(DEFAULT_FEAT)
+                    .help("Writes \"feat.\", \"ft.\", and \"featuring\" as <SPELLING> when normalizing with \"feat\""),
+            )
+            .arg(
+                Arg::with_name(FILE_TEMPLATE)
+                    .long(FILE_TEMPLATE)
+                    .takes_value(true)
+                    .value_name(TEMPLATE_VALUE)
+                    .help("Names the files according to <TEMPLATE>, e.g. \"{disc:02}-{track:02} {title}\"; available placeholders are {album}, {albumartist}, {artist}, {composer}, {disc}, {disctotal}, {format}, {genre}, {title}, {track}, {tracktotal}, and {year}"),
+            )
+            .arg(
+                Arg::with_name(INFER_FROM_PATH)
+                    .long(INFER_FROM_PATH)
+                    .help("Takes missing tags from the file's path, see --path-pattern"),
+            )
+            .arg(
+                Arg::with_name(INTERACTIVE)
+                    .short("i")
+                    .long(INTERACTIVE)
+                    .help("Shows the actions planned for each directory and asks whether to accept or skip them, to edit a new name, or to quit"),
+            )
+            .arg(
+                Arg::with_name(JOURNAL)
+                    .long(JOURNAL)
+                    .takes_value(true)
+                    .value_name(JOURNAL_VALUE)
+                    .help("Writes the journal of all renames and removals to <JOURNAL> instead of ~/.mp3rename/journal-<timestamp>.jsonl"),
+            )
+            .arg(
+                Arg::with_name(KEEP)
+                    .long(KEEP)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name(FILE_PATTERN_VALUE)
+                    .requires(REMOVE)
+                    .help("Keeps the non-music files whose names match <PATTERN>, a glob like \"*.jpg\" or a regular expression like \"re:^scan\\d+\", when removing; can be given several times"),
+            )
             .arg(
                 Arg::with_name(LENGTH)
                     .short("l")
                     .long(LENGTH)
                     .takes_value(true)
                     .value_name(LENGTH_VALUE)
-                    .help("Limits the file and directory names to <LENGTH> characters"),
+                    .help("Limits the file and directory names to <LENGTH> characters, cutting them at a word boundary if possible"),
+            )
+            .arg(
+                Arg::with_name(LENGTH_UNIT)
+                    .long(LENGTH_UNIT)
+                    .takes_value(true)
+                    .value_name(LENGTH_UNIT_VALUE)
+                    .possible_values(&["chars", "bytes"])
+                    .default_value("chars")
+                    .help("Counts the <LENGTH> of names in characters or in bytes, for file systems limiting the bytes of a name"),
+            )
+            .arg(
+                Arg::with_name(LIBRARY_PATH)
+                    .long(LIBRARY_PATH)
+                    .takes_value(true)
+                    .value_name(TEMPLATE_VALUE)
+                    .default_value(DEFAULT_LIBRARY_PATH)
+                    .help("Uses <TEMPLATE> for the directories below the library root, with \"/\" separating the directories"),
+            )
+            .arg(
+                Arg::with_name(MAX_PATH_LENGTH)
+                    .long(MAX_PATH_LENGTH)
+                    .takes_value(true)
+                    .value_name(LENGTH_VALUE)
+                    .help("Shortens the file names and the directory names mp3rename chooses so that the files' paths below the path root have at most <LENGTH> characters"),
+            )
+            .arg(
+                Arg::with_name(MOVE_TO)
+                    .long(MOVE_TO)
+                    .takes_value(true)
+                    .value_name(MOVE_TO_VALUE)
+                    .help("Moves the music files and the non-music files next to them into a library below <LIBRARY_ROOT>"),
             )
             .arg(
                 Arg::with_name(OMIT_ARTIST)
@@ -76,11 +359,84 @@ The resulting file name will have the form
                     .long(OMIT_ARTIST)
                     .help("Omit artist"),
             )
+            .arg(
+                Arg::with_name(NORMALIZE)
+                    .long(NORMALIZE)
+                    .takes_value(true)
+                    .value_name(NORMALIZE_VALUE)
+                    .help("Normalizes the tags before naming the files with the comma-separated <STEPS>: \"trim\" removes superfluous whitespace, \"title-case\" capitalizes words in all caps or lowercase, \"feat\" unifies the spelling of featured artists"),
+            )
+            .arg(
+                Arg::with_name(ON_CONFLICT)
+                    .long(ON_CONFLICT)
+                    .takes_value(true)
+                    .value_name(ON_CONFLICT_VALUE)
+                    .possible_values(&["skip", "suffix", "abort"])
+                    .default_value("skip")
+                    .help("What to do if files would get the same name: skip the file, add a suffix like \" (2)\", or leave the whole directory alone"),
+            )
+            .arg(
+                Arg::with_name(OUTPUT_FORMAT)
+                    .long(OUTPUT_FORMAT)
+                    .takes_value(true)
+                    .value_name(OUTPUT_FORMAT_VALUE)
+                    .possible_values(&["text", "json", "csv", "tsv"])
+                    .default_value("text")
+                    .help("Prints progress messages as text, or one record per planned action as JSON, CSV, or TSV instead"),
+            )
+            .arg(
+                Arg::with_name(PATH_PATTERN)
+                    .long(PATH_PATTERN)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name(PATH_PATTERN_VALUE)
+                    .requires(INFER_FROM_PATH)
+                    .help("Infers tags by matching the end of the file's path against <PATTERN>, a template like \"{artist}/{album}/{track} - {title}\" (the default); can be given several times, the first matching pattern is used"),
+            )
+            .arg(
+                Arg::with_name(PATH_ROOT)
+                    .long(PATH_ROOT)
+                    .takes_value(true)
+                    .value_name(PATH_ROOT_VALUE)
+                    .help("Measures --max-path-length from <DIR> instead of the library root or the start directory"),
+            )
+            .arg(
+                Arg::with_name(PRUNE_EMPTY_DIRS)
+                    .long(PRUNE_EMPTY_DIRS)
+                    .help("Removes the directories below the start directory that are left empty or only contain files matching --delete"),
+            )
+            .arg(
+                Arg::with_name(PURGE_TRASH_OLDER_THAN)
+                    .long(PURGE_TRASH_OLDER_THAN)
+                    .takes_value(true)
+                    .value_name(PURGE_TRASH_OLDER_THAN_VALUE)
+                    .requires(TRASH_DIR)
+                    .help("Removes the files moved to the trash directory more than <DAYS> days ago for good"),
+            )
             .arg(
                 Arg::with_name(REMOVE)
                     .short("r")
                     .long(REMOVE)
-                    .help("Removes non-music files"),
+                    .help("Removes non-music files; this cannot be undone unless they are moved to --trash-dir"),
+            )
+            .arg(
+                Arg::with_name(REPLACE)
+                    .long(REPLACE)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name(REPLACE_VALUE)
+                    .help("Replaces <FROM> with <TO> in the names before sanitizing them, e.g. \"???=Fragezeichen\"; can be given several times"),
+            )
+            .arg(
+                Arg::with_name(SANITIZE_PROFILE)
+                    .long(SANITIZE_PROFILE)
+                    .takes_value(true)
+                    .value_name(SANITIZE_PROFILE_VALUE)
+                    .possible_values(&["posix", "windows", "fat32", "smb", "strict-ascii"])
+                    .default_value("windows")
+                    .help("Makes the names valid on the file system of <PROFILE>"),
             )
             .arg(
                 // this is a positional argument
@@ -89,23 +445,134 @@ The resulting file name will have the form
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::with_name(STRIP)
+                    .long(STRIP)
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name(STRIP_VALUE)
+                    .help("Removes the matches of <REGEX> from the tags before naming the files, e.g. \"\\s*\\(Remastered \\d+\\)\"; can be given several times"),
+            )
+            .arg(
+                Arg::with_name(TITLE_CASE_EXCEPTIONS)
+                    .long(TITLE_CASE_EXCEPTIONS)
+                    .takes_value(true)
+                    .value_name(WORDS_VALUE)
+                    .default_value(DEFAULT_TITLE_CASE_EXCEPTIONS)
+                    .help("Keeps the comma-separated <WORDS> lowercase when normalizing with \"title-case\""),
+            )
+            .arg(
+                Arg::with_name(TRASH_DIR)
+                    .long(TRASH_DIR)
+                    .takes_value(true)
+                    .value_name(TRASH_DIR_VALUE)
+                    .requires(REMOVE)
+                    .help("Moves the non-music files removed with -r to <DIR>, keeping their paths below the start directory"),
+            )
+            .arg(
+                Arg::with_name(UNICODE_NORMALIZATION)
+                    .long(UNICODE_NORMALIZATION)
+                    .takes_value(true)
+                    .value_name(UNICODE_NORMALIZATION_VALUE)
+                    .possible_values(&["none", "nfc", "nfd"])
+                    .default_value("none")
+                    .help("Writes the names in the Unicode normalization <FORM>; names only differing in their normalization are left alone"),
+            )
             .arg(
                 Arg::with_name(VERBOSE)
                     .short("v")
                     .long(VERBOSE)
+                    .global(true)
                     .help("Be verbose"),
             )
+            .arg(
+                Arg::with_name(WRITE_TAGS)
+                    .long(WRITE_TAGS)
+                    .help("Writes the normalized tags back to the music files"),
+            )
+            .subcommand(
+                SubCommand::with_name(APPLY)
+                    .about("Carries out the actions of a plan written by a dry run with --output-format json")
+                    .arg(
+                        Arg::with_name(PLAN_VALUE)
+                            .help("The plan to carry out; files that have changed since are left alone")
+                            .index(1)
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name(JOURNAL)
+                            .long(JOURNAL)
+                            .takes_value(true)
+                            .value_name(JOURNAL_VALUE)
+                            .help("Writes the journal to <JOURNAL> instead of ~/.mp3rename/journal-<timestamp>.jsonl"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name(TAG_FROM_NAME)
+                    .about("Writes the tags found in the paths of the music files into their tags")
+                    .arg(
+                        Arg::with_name(OVERWRITE)
+                            .long(OVERWRITE)
+                            .help("Replaces tags that are already set instead of only adding missing ones"),
+                    )
+                    .arg(
+                        Arg::with_name(PATH_PATTERN)
+                            .long(PATH_PATTERN)
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .value_name(PATH_PATTERN_VALUE)
+                            .help("Matches the end of the file's path against <PATTERN>, a template like \"{artist}/{album}/{track} - {title}\" (the default); can be given several times, the first matching pattern is used"),
+                    )
+                    .arg(
+                        Arg::with_name(START_DIR)
+                            .help("The directory to start from")
+                            .index(1)
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name(UNDO)
+                    .about("Reverts the renames recorded in a journal; removed files cannot be restored")
+                    .arg(
+                        Arg::with_name(JOURNAL_VALUE)
+                            .help("The journal written by a previous run")
+                            .index(1)
+                            .required(true),
+                    ),
+            )
 
             .get_matches();
 
-        // the directory is mandatory
-        let start_dir = matches.value_of(START_DIR).unwrap();
-        let start_dir = match util::string_to_path(start_dir) {
-            Ok(path) => path,
-            Err(_) => {
-                eprintln!("Couldn't find the path \"{}\"", start_dir);
-                process::exit(1);
+        let command = match matches.subcommand() {
+            (APPLY, Some(apply_matches)) => {
+                Command::Apply(PathBuf::from(apply_matches.value_of(PLAN_VALUE).unwrap()))
+            }
+            (TAG_FROM_NAME, Some(_)) => Command::TagFromName,
+            (UNDO, Some(undo_matches)) => {
+                Command::Undo(PathBuf::from(undo_matches.value_of(JOURNAL_VALUE).unwrap()))
             }
+            _ => Command::Rename,
+        };
+
+        // the directory is mandatory for renaming and tagging
+        let start_dir = match command {
+            Command::Rename | Command::TagFromName => {
+                let start_dir = matches
+                    .subcommand_matches(TAG_FROM_NAME)
+                    .unwrap_or(&matches)
+                    .value_of(START_DIR)
+                    .unwrap();
+                match util::string_to_path(start_dir) {
+                    Ok(path) => path,
+                    Err(_) => {
+                        eprintln!("Couldn't find the path \"{}\"", start_dir);
+                        process::exit(1);
+                    }
+                }
+            }
+            Command::Undo(_) | Command::Apply(_) => PathBuf::new(),
         };
 
         let name_length = match matches.value_of(LENGTH) {
@@ -118,27 +585,335 @@ The resulting file name will have the form
                 }
             },
         };
+        let max_path_length = match matches.value_of(MAX_PATH_LENGTH) {
+            None => 0,
+            Some(num) => match num.parse::<usize>() {
+                Ok(val) => val,
+                Err(_) => {
+                    eprintln!("Cannot parse length \"{}\"", num);
+                    process::exit(1);
+                }
+            },
+        };
+        let purge_trash_older_than = matches.value_of(PURGE_TRASH_OLDER_THAN).map(|days| {
+            days.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("Cannot parse number of days \"{}\"", days);
+                process::exit(1);
+            })
+        });
+
+        // the library root doesn't need to exist yet
+        let library_root = matches.value_of(MOVE_TO).map(|library_root| {
+            util::string_to_path(library_root).unwrap_or_else(|_| {
+                env::current_dir()
+                    .unwrap_or_default()
+                    .join(PathBuf::from(library_root))
+            })
+        });
+        let library_path = matches
+            .value_of(LIBRARY_PATH)
+            .unwrap_or(DEFAULT_LIBRARY_PATH)
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .filter_map(|component| Config::parse_template(Some(component)))
+            .collect();
+
+        let on_conflict = matches
+            .value_of(ON_CONFLICT)
+            .and_then(ConflictPolicy::from_name)
+            .unwrap_or_default();
+
+        let extensions = Config::parse_extensions(matches.value_of(EXTENSIONS)).without(
+            &Config::parse_extensions(matches.value_of(EXCLUDE_EXTENSIONS)),
+        );
+
+        let output_format = matches
+            .value_of(OUTPUT_FORMAT)
+            .and_then(OutputFormat::from_name)
+            .unwrap_or_default();
+
+        // tagging always matches the paths, renaming only if asked to
+        let path_patterns = if let Some(tag_matches) = matches.subcommand_matches(TAG_FROM_NAME) {
+            tag_matches
+                .values_of(PATH_PATTERN)
+                .map(|patterns| patterns.collect())
+                .unwrap_or_else(|| vec![DEFAULT_PATH_PATTERN])
+                .into_iter()
+                .map(Config::parse_path_pattern)
+                .collect()
+        } else if matches.is_present(INFER_FROM_PATH) {
+            matches
+                .values_of(PATH_PATTERN)
+                .map(|patterns| patterns.collect())
+                .unwrap_or_else(|| vec![DEFAULT_PATH_PATTERN])
+                .into_iter()
+                .map(Config::parse_path_pattern)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut normalization = Normalization {
+            strip: matches
+                .values_of(STRIP)
+                .map(|regexes| regexes.map(Config::parse_regex).collect())
+                .unwrap_or_default(),
+            ..Normalization::default()
+        };
+        for step in matches.value_of(NORMALIZE).unwrap_or_default().split(',') {
+            match step.trim() {
+                "" => {}
+                "feat" => {
+                    normalization.feat =
+                        Some(matches.value_of(FEAT).unwrap_or(DEFAULT_FEAT).to_string())
+                }
+                "title-case" => {
+                    normalization.title_case = Some(
+                        matches
+                            .value_of(TITLE_CASE_EXCEPTIONS)
+                            .unwrap_or(DEFAULT_TITLE_CASE_EXCEPTIONS)
+                            .split(',')
+                            .map(|word| word.trim().to_lowercase())
+                            .filter(|word| !word.is_empty())
+                            .collect(),
+                    )
+                }
+                "trim" => normalization.trim = true,
+                step => {
+                    eprintln!("Unknown normalization step \"{}\"", step);
+                    process::exit(1);
+                }
+            }
+        }
+
+        let dir_template = Config::parse_template(matches.value_of(DIR_TEMPLATE));
+        let file_template = Config::parse_template(matches.value_of(FILE_TEMPLATE));
 
         Config {
+            ascii: if matches.is_present(ASCII) {
+                Some(
+                    matches
+                        .value_of(ASCII_LOCALE)
+                        .and_then(Locale::from_name)
+                        .unwrap_or_default(),
+                )
+            } else {
+                None
+            },
+            command,
+            compilation_dir_artist: matches
+                .value_of(COMPILATION_DIR_ARTIST)
+                .unwrap_or(DEFAULT_COMPILATION_DIR_ARTIST)
+                .to_string(),
+            cover_name: matches.value_of(COVER_NAME).map(String::from),
+            delete_patterns: Config::parse_file_patterns(matches.values_of(DELETE)),
+            dir_template,
             dry_run: matches.is_present(DRY_RUN),
+            ellipsis: matches.is_present(ELLIPSIS),
+            extensions,
+            file_template,
+            interactive: matches.is_present(INTERACTIVE),
+            journal: matches
+                .subcommand_matches(APPLY)
+                .unwrap_or(&matches)
+                .value_of(JOURNAL)
+                .map(|journal| {
+                    // a journal must only record a single run to be undone
+                    if Path::new(journal).exists() {
+                        eprintln!("The journal \"{}\" already exists", journal);
+                        process::exit(1);
+                    }
+                    PathBuf::from(journal)
+                }),
+            keep_patterns: Config::parse_file_patterns(matches.values_of(KEEP)),
+            length_unit: matches
+                .value_of(LENGTH_UNIT)
+                .and_then(LengthUnit::from_name)
+                .unwrap_or_default(),
+            library_path,
+            library_root,
+            max_path_length,
             name_length,
+            normalization,
             omit_artist: matches.is_present(OMIT_ARTIST),
+            on_conflict,
+            output_format,
+            overwrite_tags: matches
+                .subcommand_matches(TAG_FROM_NAME)
+                .is_some_and(|tag_matches| tag_matches.is_present(OVERWRITE)),
+            path_patterns,
+            prune_empty_dirs: matches.is_present(PRUNE_EMPTY_DIRS),
+            purge_trash_older_than,
+            path_root: matches.value_of(PATH_ROOT).map(|path_root| {
+                util::string_to_path(path_root).unwrap_or_else(|_| {
+                    eprintln!("Couldn't find the path \"{}\"", path_root);
+                    process::exit(1);
+                })
+            }),
             remove_artist: matches.is_present(ARTIST),
             remove_ordinary_files: matches.is_present(REMOVE),
             rename_directory: matches.is_present(DIRECTORY),
+            replacements: matches
+                .values_of(REPLACE)
+                .map(|replacements| replacements.map(Config::parse_replacement).collect())
+                .unwrap_or_default(),
+            sanitize_profile: matches
+                .value_of(SANITIZE_PROFILE)
+                .and_then(SanitizeProfile::from_name)
+                .unwrap_or_default(),
             shorten_names: matches.is_present(LENGTH),
             start_dir,
+            // the trash directory doesn't need to exist yet
+            trash_dir: matches.value_of(TRASH_DIR).map(|trash_dir| {
+                util::string_to_path(trash_dir).unwrap_or_else(|_| {
+                    env::current_dir()
+                        .unwrap_or_default()
+                        .join(PathBuf::from(trash_dir))
+                })
+            }),
+            unicode_normalization: matches
+                .value_of(UNICODE_NORMALIZATION)
+                .and_then(UnicodeNormalization::from_name)
+                .unwrap_or_default(),
             verbose: matches.is_present(VERBOSE),
+            write_tags: matches.is_present(WRITE_TAGS),
+        }
+    }
+
+    fn parse_extensions(list: Option<&str>) -> Extensions {
+        match list {
+            None => Extensions::new(Vec::<String>::new()),
+            Some(list) => Extensions::parse(list).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            }),
+        }
+    }
+
+    fn parse_file_patterns(patterns: Option<clap::Values>) -> Vec<FilePattern> {
+        patterns
+            .map(|patterns| {
+                patterns
+                    .map(|pattern| {
+                        FilePattern::parse(pattern).unwrap_or_else(|err| {
+                            eprintln!("Cannot parse file pattern \"{}\": {}", pattern, err);
+                            process::exit(1);
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse_path_pattern(pattern: &str) -> PathPattern {
+        PathPattern::parse(pattern).unwrap_or_else(|err| {
+            eprintln!("Cannot parse path pattern \"{}\": {}", pattern, err);
+            process::exit(1);
+        })
+    }
+
+    fn parse_regex(regex: &str) -> Regex {
+        Regex::new(regex).unwrap_or_else(|err| {
+            eprintln!("Cannot parse regular expression \"{}\": {}", regex, err);
+            process::exit(1);
+        })
+    }
+
+    fn parse_replacement(replacement: &str) -> (String, String) {
+        sanitize::parse_replacement(replacement).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        })
+    }
+
+    fn parse_template(template: Option<&str>) -> Option<Template> {
+        match template {
+            None => None,
+            Some(template) => match Template::parse(template) {
+                Ok(template) => Some(template),
+                Err(err) => {
+                    eprintln!("Cannot parse template \"{}\": {}", template, err);
+                    process::exit(1);
+                }
+            },
         }
     }
 }
 
 impl fmt::Display for Config {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ASCII transliteration:    {:?}", self.ascii)?;
+        writeln!(f, "Command:                  {:?}", self.command)?;
+        writeln!(
+            f,
+            "Compilation artist:       {:?}",
+            self.compilation_dir_artist
+        )?;
+        writeln!(
+            f,
+            "Directory template:       {:?}",
+            self.dir_template.as_ref().map(|t| t.to_string())
+        )?;
+        writeln!(f, "Cover name:               {:?}", self.cover_name)?;
+        writeln!(
+            f,
+            "Delete patterns:          {:?}",
+            self.delete_patterns
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+        )?;
         writeln!(f, "Dry run:                  {:?}", self.dry_run)?;
+        writeln!(f, "Ellipsis:                 {:?}", self.ellipsis)?;
+        writeln!(f, "Extensions:               {}", self.extensions)?;
         writeln!(f, "Using path                {:?}", self.start_dir)?;
+        writeln!(
+            f,
+            "File template:            {:?}",
+            self.file_template.as_ref().map(|t| t.to_string())
+        )?;
+        writeln!(
+            f,
+            "Keep patterns:            {:?}",
+            self.keep_patterns
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+        )?;
+        writeln!(f, "Interactive:              {:?}", self.interactive)?;
+        writeln!(f, "Library root:             {:?}", self.library_root)?;
+        writeln!(
+            f,
+            "Library path:             {:?}",
+            self.library_path
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<String>>()
+                .join("/")
+        )?;
+        writeln!(f, "Max. path length:         {:?}", self.max_path_length)?;
         writeln!(f, "Name length limit:        {:?}", self.name_length)?;
+        writeln!(f, "Name length unit:         {:?}", self.length_unit)?;
+        writeln!(f, "Normalization:            {:?}", self.normalization)?;
         writeln!(f, "Omit artist:              {:?}", self.omit_artist)?;
+        writeln!(f, "On conflict:              {:?}", self.on_conflict)?;
+        writeln!(f, "Output format:            {:?}", self.output_format)?;
+        writeln!(f, "Overwrite tags:           {:?}", self.overwrite_tags)?;
+        writeln!(
+            f,
+            "Path patterns:            {:?}",
+            self.path_patterns
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+        )?;
+        writeln!(f, "Path root:                {:?}", self.path_root)?;
+        writeln!(f, "Prune empty directories:  {:?}", self.prune_empty_dirs)?;
+        writeln!(
+            f,
+            "Purge trash older than:   {:?}",
+            self.purge_trash_older_than
+        )?;
         writeln!(f, "Remove artist:            {:?}", self.remove_artist)?;
         writeln!(
             f,
@@ -146,7 +921,16 @@ impl fmt::Display for Config {
             self.remove_ordinary_files
         )?;
         writeln!(f, "Rename directory:         {:?}", self.rename_directory)?;
+        writeln!(f, "Replacements:             {:?}", self.replacements)?;
+        writeln!(f, "Sanitize profile:         {:?}", self.sanitize_profile)?;
         writeln!(f, "Shorten names:            {:?}", self.shorten_names)?;
-        writeln!(f, "Verbose mode:             {:?}", self.verbose)
+        writeln!(f, "Trash directory:          {:?}", self.trash_dir)?;
+        writeln!(
+            f,
+            "Unicode normalization:    {:?}",
+            self.unicode_normalization
+        )?;
+        writeln!(f, "Verbose mode:             {:?}", self.verbose)?;
+        writeln!(f, "Write tags:               {:?}", self.write_tags)
     }
 }
@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+
+/// What to do if two files would get the same name or a name that is already taken
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// Leave the conflicting file alone
+    #[default]
+    Skip,
+    /// Append " (2)", " (3)", ... to the conflicting file's name
+    Suffix,
+    /// Don't rename anything in the directory
+    Abort,
+}
+
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Option<ConflictPolicy> {
+        match name {
+            "skip" => Some(ConflictPolicy::Skip),
+            "suffix" => Some(ConflictPolicy::Suffix),
+            "abort" => Some(ConflictPolicy::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// A file or directory to be renamed or moved
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rename {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+}
+
+impl Rename {
+    pub fn new(old_path: PathBuf, new_path: PathBuf) -> Rename {
+        Rename { old_path, new_path }
+    }
+}
+
+/// Why a rename cannot be done as planned
+#[derive(Debug, PartialEq)]
+pub enum Conflict {
+    /// Another file gets the same new name
+    SameName {
+        old_path: PathBuf,
+        other_old_path: PathBuf,
+        new_path: PathBuf,
+    },
+    /// The new name is taken by a file that stays where it is
+    Exists {
+        old_path: PathBuf,
+        new_path: PathBuf,
+    },
+}
+
+impl Conflict {
+    /// Returns the path of the file that cannot be renamed as planned
+    pub fn old_path(&self) -> &Path {
+        match self {
+            Conflict::SameName { old_path, .. } | Conflict::Exists { old_path, .. } => old_path,
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::SameName {
+                old_path,
+                other_old_path,
+                new_path,
+            } => write!(
+                f,
+                "\"{}\" and \"{}\" would both be renamed to \"{}\"",
+                other_old_path.to_string_lossy(),
+                old_path.to_string_lossy(),
+                new_path.to_string_lossy()
+            ),
+            Conflict::Exists { old_path, new_path } => write!(
+                f,
+                "\"{}\" would be renamed to \"{}\", which already exists",
+                old_path.to_string_lossy(),
+                new_path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// Checks all renames of a directory at once and resolves name collisions according to the policy.
+/// The renames are checked in order, so the first one gets the contested name.
+/// Returns the renames to be done and the conflicts that were resolved, or all conflicts if
+/// the policy is to abort.
+pub fn resolve_conflicts(
+    renames: Vec<Rename>,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Rename>, Vec<Conflict>), Vec<Conflict>> {
+    // Files that are renamed make room for others, but a skipped file stays where it is
+    // and may block another file's new name. So repeat until no more files are skipped.
+    let mut moving: HashSet<PathBuf> = renames.iter().map(|r| r.old_path.clone()).collect();
+    let mut all_conflicts = Vec::new();
+    loop {
+        let (resolved, conflicts) = resolve_conflicts_once(&renames, &moving, policy);
+
+        if policy == ConflictPolicy::Abort && !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+        all_conflicts.extend(conflicts);
+        if resolved.len() == moving.len() {
+            return Ok((resolved, all_conflicts));
+        }
+        moving = resolved.iter().map(|r| r.old_path.clone()).collect();
+    }
+}
+
+fn resolve_conflicts_once(
+    renames: &[Rename],
+    moving: &HashSet<PathBuf>,
+    policy: ConflictPolicy,
+) -> (Vec<Rename>, Vec<Conflict>) {
+    let staying: HashSet<PathBuf> = renames
+        .iter()
+        .map(|r| r.old_path.clone())
+        .filter(|p| !moving.contains(p))
+        .collect();
+    let mut taken: Vec<(PathBuf, PathBuf)> = Vec::new(); // new path and the old path that took it
+    let mut resolved = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for rename in renames.iter().filter(|r| moving.contains(&r.old_path)) {
+        match find_conflict(rename, &taken, moving, &staying) {
+            None => {
+                taken.push((rename.new_path.clone(), rename.old_path.clone()));
+                resolved.push(rename.clone());
+            }
+            Some(conflict) => {
+                if policy == ConflictPolicy::Suffix {
+                    let new_path = (2..)
+                        .map(|n| with_suffix(&rename.old_path, &rename.new_path, n))
+                        .find(|new_path| {
+                            find_conflict(
+                                &Rename::new(rename.old_path.clone(), new_path.clone()),
+                                &taken,
+                                moving,
+                                &staying,
+                            )
+                            .is_none()
+                        })
+                        .unwrap();
+                    taken.push((new_path.clone(), rename.old_path.clone()));
+                    resolved.push(Rename::new(rename.old_path.clone(), new_path));
+                }
+                conflicts.push(conflict);
+            }
+        }
+    }
+
+    (resolved, conflicts)
+}
+
+fn find_conflict(
+    rename: &Rename,
+    taken: &[(PathBuf, PathBuf)],
+    moving: &HashSet<PathBuf>,
+    staying: &HashSet<PathBuf>,
+) -> Option<Conflict> {
+    if let Some((_, other_old_path)) = taken.iter().find(|(p, _)| *p == rename.new_path) {
+        return Some(Conflict::SameName {
+            old_path: rename.old_path.clone(),
+            other_old_path: other_old_path.clone(),
+            new_path: rename.new_path.clone(),
+        });
+    }
+
+    if rename.new_path != rename.old_path
+        && !moving.contains(&rename.new_path)
+        && (rename.new_path.exists() || staying.contains(&rename.new_path))
+        // a case-insensitive file system finds the file itself under its new name
+        && !same_file::is_same_file(&rename.old_path, &rename.new_path).unwrap_or(false)
+    {
+        return Some(Conflict::Exists {
+            old_path: rename.old_path.clone(),
+            new_path: rename.new_path.clone(),
+        });
+    }
+
+    None
+}
+
+/// Inserts " (<n>)" before a file's extension or at the end of a directory's name
+fn with_suffix(old_path: &Path, new_path: &Path, n: u32) -> PathBuf {
+    let name = new_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match new_path.extension() {
+        Some(extension) if !old_path.is_dir() => {
+            let extension = format!(".{}", extension.to_string_lossy());
+            format!("{} ({}){}", name.trim_end_matches(&extension), n, extension)
+        }
+        _ => format!("{} ({})", name, n),
+    };
+    new_path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn rename(old_name: &str, new_name: &str) -> Rename {
+        Rename::new(
+            PathBuf::from(format!("/nonexistent/{}", old_name)),
+            PathBuf::from(format!("/nonexistent/{}", new_name)),
+        )
+    }
+
+    #[test]
+    fn test_no_conflicts() {
+        let renames = vec![rename("a.mp3", "1 A.mp3"), rename("b.mp3", "2 B.mp3")];
+        assert_eq!(
+            resolve_conflicts(renames.clone(), ConflictPolicy::Abort),
+            Ok((renames, vec![]))
+        );
+    }
+
+    #[test]
+    fn test_same_name() {
+        let renames = vec![rename("a.mp3", "1 A.mp3"), rename("b.mp3", "1 A.mp3")];
+        let conflict = Conflict::SameName {
+            old_path: PathBuf::from("/nonexistent/b.mp3"),
+            other_old_path: PathBuf::from("/nonexistent/a.mp3"),
+            new_path: PathBuf::from("/nonexistent/1 A.mp3"),
+        };
+
+        let (resolved, conflicts) =
+            resolve_conflicts(renames.clone(), ConflictPolicy::Skip).unwrap();
+        assert_eq!(resolved, vec![rename("a.mp3", "1 A.mp3")]);
+        assert_eq!(conflicts.len(), 1);
+
+        let (resolved, _) = resolve_conflicts(renames.clone(), ConflictPolicy::Suffix).unwrap();
+        assert_eq!(
+            resolved,
+            vec![rename("a.mp3", "1 A.mp3"), rename("b.mp3", "1 A (2).mp3")]
+        );
+
+        assert_eq!(
+            resolve_conflicts(renames, ConflictPolicy::Abort),
+            Err(vec![conflict])
+        );
+    }
+
+    #[test]
+    fn test_suffix_skips_taken_names() {
+        let renames = vec![
+            rename("a.mp3", "1 A.mp3"),
+            rename("b.mp3", "1 A (2).mp3"),
+            rename("c.mp3", "1 A.mp3"),
+        ];
+        let (resolved, _) = resolve_conflicts(renames, ConflictPolicy::Suffix).unwrap();
+        assert_eq!(resolved[2], rename("c.mp3", "1 A (3).mp3"));
+    }
+
+    #[test]
+    fn test_existing_file() {
+        let temp_dir = TempDir::new("conflict");
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("a.mp3"), "a").unwrap();
+        std::fs::write(dir.join("b.mp3"), "b").unwrap();
+        std::fs::write(dir.join("cover.jpg"), "c").unwrap();
+
+        // a file that is renamed itself doesn't block its name
+        let renames = vec![
+            Rename::new(dir.join("a.mp3"), dir.join("b.mp3")),
+            Rename::new(dir.join("b.mp3"), dir.join("cover.jpg")),
+        ];
+        let result = resolve_conflicts(renames, ConflictPolicy::Abort);
+        assert_eq!(
+            result,
+            Err(vec![Conflict::Exists {
+                old_path: dir.join("b.mp3"),
+                new_path: dir.join("cover.jpg"),
+            }])
+        );
+
+        // "b.mp3" cannot be renamed, so "a.mp3" cannot take its name
+        let renames = vec![
+            Rename::new(dir.join("a.mp3"), dir.join("b.mp3")),
+            Rename::new(dir.join("b.mp3"), dir.join("cover.jpg")),
+        ];
+        let (resolved, conflicts) = resolve_conflicts(renames, ConflictPolicy::Skip).unwrap();
+        assert_eq!(resolved, vec![]);
+        assert_eq!(conflicts.len(), 2);
+    }
+}
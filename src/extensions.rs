@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fmt::Formatter;
+
+/// The extensions of all music files whose tags can be read
+pub const SUPPORTED_EXTENSIONS: [&str; 13] = [
+    "mp3", "flac", "m4a", "m4b", "m4p", "m4v", "ogg", "oga", "opus", "wav", "aif", "aiff", "wv",
+];
+
+/// The extensions of the files treated as music files, lowercase and without leading dot.
+/// By default, these are all supported extensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extensions(Vec<String>);
+
+impl Default for Extensions {
+    fn default() -> Extensions {
+        Extensions::new(SUPPORTED_EXTENSIONS)
+    }
+}
+
+impl Extensions {
+    /// Normalizes the extensions, so ".MP3" and "mp3" are the same
+    pub fn new<I, S>(extensions: I) -> Extensions
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut result: Vec<String> = Vec::new();
+        for extension in extensions {
+            let extension = extension
+                .as_ref()
+                .trim()
+                .trim_start_matches('.')
+                .to_lowercase();
+            if !extension.is_empty() && !result.contains(&extension) {
+                result.push(extension);
+            }
+        }
+        Extensions(result)
+    }
+
+    /// Parses a comma-separated list like "mp3,flac"
+    pub fn parse(list: &str) -> Result<Extensions, String> {
+        let extensions = Extensions::new(list.split(','));
+        match extensions
+            .0
+            .iter()
+            .find(|ext| !SUPPORTED_EXTENSIONS.contains(&ext.as_str()))
+        {
+            Some(ext) => Err(format!("Unsupported extension \"{}\"", ext)),
+            None => Ok(extensions),
+        }
+    }
+
+    /// Removes the given extensions
+    pub fn without(self, excluded: &Extensions) -> Extensions {
+        Extensions(
+            self.0
+                .into_iter()
+                .filter(|ext| !excluded.0.contains(ext))
+                .collect(),
+        )
+    }
+
+    /// Checks if a name ends with one of the extensions, ignoring case
+    pub fn matches(&self, file_name: &str) -> bool {
+        let file_name = file_name.to_lowercase();
+        self.0
+            .iter()
+            .any(|ext| file_name.ends_with(&format!(".{}", ext)))
+    }
+}
+
+/// Lists the extensions like `mp3|flac|m4a`
+impl fmt::Display for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join("|"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Extensions::parse(" .MP3,flac,,mp3"),
+            Ok(Extensions::new(["mp3", "flac"]))
+        );
+        assert!(Extensions::parse("mp3,txt").is_err());
+    }
+
+    #[test]
+    fn test_without() {
+        let extensions = Extensions::default().without(&Extensions::new(["m4v"]));
+        assert!(extensions.matches("/tmp/music.m4a"));
+        assert!(!extensions.matches("/tmp/video.m4v"));
+    }
+
+    #[test]
+    fn test_readme_lists_the_supported_extensions() {
+        let readme = std::fs::read_to_string("README.md").unwrap();
+        assert!(readme.contains(&format!(
+            "with an extension in `<{}>`",
+            Extensions::default()
+        )));
+    }
+}
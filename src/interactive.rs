@@ -0,0 +1,332 @@
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::conflict;
+use crate::conflict::{ConflictPolicy, Rename};
+use crate::journal::Action;
+use crate::rename_plan::PlannedAction;
+
+/// What the user decided to do with a directory
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Carry out the renames, possibly edited
+    Accept(Vec<Rename>),
+    /// Leave the directory alone
+    Skip,
+    /// Leave this and all remaining directories alone
+    Quit,
+}
+
+/// Shows the renames planned for a directory as a table and asks the user to accept or skip
+/// them, to edit a new name, or to quit. Edited names are checked for conflicts right away.
+/// The table also lists the other actions planned along with the renames, like renaming
+/// sidecars or removing files, which `other_actions` returns for the renames as edited.
+/// The user is asked whenever there is anything to do.
+pub fn confirm<R: BufRead, W: Write>(
+    dir: &Path,
+    mut renames: Vec<Rename>,
+    other_actions: impl Fn(&[Rename]) -> Vec<PlannedAction>,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<Decision> {
+    if renames.is_empty() && other_actions(&renames).is_empty() {
+        return Ok(Decision::Accept(renames));
+    }
+
+    loop {
+        print_table(dir, &renames, &other_actions(&renames), output)?;
+        let answer = match ask(
+            "[a]ccept, [s]kip, [e]dit <number>, or [q]uit? ",
+            input,
+            output,
+        )? {
+            Some(answer) => answer,
+            // there's nobody to ask anymore
+            None => return Ok(Decision::Quit),
+        };
+
+        let mut words = answer.split_whitespace();
+        match words.next().map(|word| word.to_lowercase()).as_deref() {
+            Some("a") | Some("accept") => return Ok(Decision::Accept(renames)),
+            Some("s") | Some("skip") => return Ok(Decision::Skip),
+            Some("q") | Some("quit") => return Ok(Decision::Quit),
+            Some("e") | Some("edit") if renames.is_empty() => {
+                writeln!(output, "There are no names to edit")?
+            }
+            Some("e") | Some("edit") => {
+                let number = match words.next() {
+                    Some(number) => Some(number.to_string()),
+                    None if renames.len() == 1 => Some(String::from("1")),
+                    None => ask("Number: ", input, output)?,
+                };
+                match number.and_then(|n| n.trim().parse::<usize>().ok()) {
+                    Some(n) if (1..=renames.len()).contains(&n) => {
+                        edit(&mut renames, n - 1, input, output)?
+                    }
+                    _ => writeln!(output, "Please give a number from 1 to {}", renames.len())?,
+                }
+            }
+            _ => writeln!(output, "Please answer a, s, e, or q")?,
+        }
+    }
+}
+
+/// Asks for the new name of a single rename, keeping the old one if the new one is empty,
+/// invalid, or conflicts with another name
+fn edit<R: BufRead, W: Write>(
+    renames: &mut [Rename],
+    index: usize,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<()> {
+    let question = format!("New name for \"{}\": ", file_name(&renames[index].old_path));
+    let name = match ask(&question, input, output)? {
+        Some(name) if !name.is_empty() => name,
+        _ => return Ok(()),
+    };
+    if name.contains('/') || name.contains('\\') || name == "." || name == ".." {
+        return writeln!(output, "\"{}\" is not a valid name", name);
+    }
+
+    let mut edited = renames.to_vec();
+    edited[index].new_path = edited[index].new_path.with_file_name(&name);
+    match conflict::resolve_conflicts(edited, ConflictPolicy::Abort) {
+        Ok((edited, _)) => renames.clone_from_slice(&edited),
+        Err(conflicts) => {
+            for conflict in conflicts {
+                writeln!(output, "Conflict: {}", conflict)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the renames numbered and aligned, with the names relative to the directory,
+/// followed by the other actions without numbers
+fn print_table<W: Write>(
+    dir: &Path,
+    renames: &[Rename],
+    other_actions: &[PlannedAction],
+    output: &mut W,
+) -> io::Result<()> {
+    let mut rows: Vec<(String, String, String)> = renames
+        .iter()
+        .enumerate()
+        .map(|(i, rename)| {
+            (
+                (i + 1).to_string(),
+                relative_name(dir, &rename.old_path),
+                format!("->  {}", relative_name(dir, &rename.new_path)),
+            )
+        })
+        .collect();
+    rows.extend(other_actions.iter().map(|planned_action| {
+        (
+            String::new(),
+            relative_name(dir, &planned_action.old_path),
+            describe(dir, planned_action),
+        )
+    }));
+    let number_width = renames.len().to_string().len();
+    let name_width = rows
+        .iter()
+        .map(|(_, old_name, _)| old_name.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    for (number, old_name, action) in rows {
+        writeln!(
+            output,
+            "{:>number_width$}  {:<name_width$}  {}",
+            number,
+            old_name,
+            action,
+            number_width = number_width,
+            name_width = name_width
+        )?;
+    }
+    Ok(())
+}
+
+/// Describes what an action does to its file or directory
+fn describe(dir: &Path, planned_action: &PlannedAction) -> String {
+    match (planned_action.action, &planned_action.new_path) {
+        (Action::Rename, Some(new_path)) => format!("->  {}", relative_name(dir, new_path)),
+        (Action::CreateDirectory, _) => String::from("(create directory)"),
+        (Action::RemoveDirectory, _) => String::from("(remove directory)"),
+        (Action::Trash, Some(_)) => String::from("(move to the trash)"),
+        (Action::Remove, _) | (Action::Rename, None) | (Action::Trash, None) => {
+            String::from("(remove)")
+        }
+        (Action::WriteTags, _) => match &planned_action.tags {
+            Some(changes) => format!("(write tags {})", changes),
+            None => String::from("(write tags)"),
+        },
+    }
+}
+
+/// Prints the question and returns the trimmed answer, or `None` at the end of the input
+fn ask<R: BufRead, W: Write>(
+    question: &str,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<Option<String>> {
+    write!(output, "{}", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// Returns the path relative to the directory, or relative to its parent for the
+/// directory itself and its new name
+fn relative_name(dir: &Path, path: &Path) -> String {
+    let parent = dir.parent().unwrap_or(dir);
+    match (path.strip_prefix(dir), path.strip_prefix(parent)) {
+        (Ok(rest), _) if !rest.as_os_str().is_empty() => rest.to_string_lossy().to_string(),
+        (_, Ok(rest)) if !rest.as_os_str().is_empty() => rest.to_string_lossy().to_string(),
+        _ => path.to_string_lossy().to_string(),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn renames() -> Vec<Rename> {
+        vec![
+            Rename::new(
+                PathBuf::from("/nonexistent/a.mp3"),
+                PathBuf::from("/nonexistent/01 Foo.mp3"),
+            ),
+            Rename::new(
+                PathBuf::from("/nonexistent/bb.mp3"),
+                PathBuf::from("/nonexistent/02 Bar.mp3"),
+            ),
+        ]
+    }
+
+    fn confirm_with(answers: &str) -> (Decision, String) {
+        let mut output = Vec::new();
+        let decision = confirm(
+            Path::new("/nonexistent"),
+            renames(),
+            |_: &[Rename]| Vec::new(),
+            &mut answers.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        (decision, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_accept_skip_and_quit() {
+        let (decision, output) = confirm_with("a\n");
+        assert_eq!(decision, Decision::Accept(renames()));
+        assert!(output.starts_with("1  a.mp3   ->  01 Foo.mp3\n2  bb.mp3  ->  02 Bar.mp3\n"));
+
+        assert_eq!(confirm_with("skip\n").0, Decision::Skip);
+        assert_eq!(confirm_with("q\n").0, Decision::Quit);
+        // nobody answers
+        assert_eq!(confirm_with("").0, Decision::Quit);
+        assert_eq!(confirm_with("x\na\n").0, Decision::Accept(renames()));
+    }
+
+    #[test]
+    fn test_edit() {
+        let (decision, _) = confirm_with("e 2\n02 Bar (Live).mp3\na\n");
+        let mut expected = renames();
+        expected[1].new_path = PathBuf::from("/nonexistent/02 Bar (Live).mp3");
+        assert_eq!(decision, Decision::Accept(expected));
+
+        // names taken by another file are refused
+        let (decision, output) = confirm_with("e\n2\n01 Foo.mp3\na\n");
+        assert_eq!(decision, Decision::Accept(renames()));
+        assert!(output.contains("Conflict: "));
+
+        let (decision, output) = confirm_with("e 3\ne 1\nfoo/bar.mp3\na\n");
+        assert_eq!(decision, Decision::Accept(renames()));
+        assert!(output.contains("Please give a number from 1 to 2"));
+        assert!(output.contains("\"foo/bar.mp3\" is not a valid name"));
+    }
+
+    #[test]
+    fn test_other_actions() {
+        // the lyrics follow the first track's new name, even when it is edited
+        let other_actions = |renames: &[Rename]| {
+            let name = renames[0].new_path.file_stem().unwrap().to_string_lossy();
+            vec![
+                PlannedAction {
+                    action: Action::Rename,
+                    old_path: PathBuf::from("/nonexistent/a.lrc"),
+                    new_path: Some(PathBuf::from(format!("/nonexistent/{}.lrc", name))),
+                    tags: None,
+                },
+                PlannedAction {
+                    action: Action::Remove,
+                    old_path: PathBuf::from("/nonexistent/Thumbs.db"),
+                    new_path: None,
+                    tags: None,
+                },
+            ]
+        };
+        let mut output = Vec::new();
+        let decision = confirm(
+            Path::new("/nonexistent"),
+            renames(),
+            other_actions,
+            &mut "e 1\n01 Foo (Live).mp3\na\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(matches!(decision, Decision::Accept(_)));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "1  a.mp3      ->  01 Foo.mp3\n\
+             2  bb.mp3     ->  02 Bar.mp3\n   \
+             a.lrc      ->  01 Foo.lrc\n   \
+             Thumbs.db  (remove)\n"
+        ));
+        assert!(output.contains("   a.lrc      ->  01 Foo (Live).lrc\n"));
+
+        // a directory without renames is still shown if there is anything else to do
+        let remove_only = |_: &[Rename]| other_actions(&renames())[1..].to_vec();
+        let mut output = Vec::new();
+        let decision = confirm(
+            Path::new("/nonexistent"),
+            Vec::new(),
+            remove_only,
+            &mut "e\ns\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(decision, Decision::Skip);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("   Thumbs.db  (remove)\n"));
+        assert!(output.contains("There are no names to edit"));
+
+        // with nothing to do, nobody is asked
+        let mut output = Vec::new();
+        let decision = confirm(
+            Path::new("/nonexistent"),
+            Vec::new(),
+            |_: &[Rename]| Vec::new(),
+            &mut "".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(decision, Decision::Accept(Vec::new()));
+        assert!(output.is_empty());
+    }
+}
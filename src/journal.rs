@@ -0,0 +1,386 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::util;
+
+/// What happened to a file or directory
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    CreateDirectory,
+    Remove,
+    /// An empty directory has been removed
+    RemoveDirectory,
+    Rename,
+    /// Moved to the trash directory instead of being removed
+    Trash,
+    /// The tags of a music file have been changed
+    WriteTags,
+}
+
+/// One line of the journal
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub action: Action,
+    pub old_path: PathBuf,
+    pub new_path: Option<PathBuf>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Records every change to the file system, one JSON object per line
+pub struct Journal {
+    path: PathBuf,
+    /// Whether a number is appended to the default path if it is taken
+    numbered: bool,
+    file: Option<File>,
+    enabled: bool,
+}
+
+impl Journal {
+    /// Creates a journal that is written to the configured path or, by default, to
+    /// `~/.mp3rename/journal-<timestamp>.jsonl`. The file is created with the first entry and
+    /// never shared with another run: if a run in the same second took the default name, a
+    /// number is appended. In dry-run mode, nothing is recorded.
+    pub fn new(config: &Config) -> Journal {
+        let (path, numbered) = match &config.journal {
+            Some(path) => (path.clone(), false),
+            None => (
+                default_journal_dir().join(format!("journal-{}.jsonl", now())),
+                true,
+            ),
+        };
+
+        Journal {
+            path,
+            numbered,
+            file: None,
+            enabled: !config.dry_run,
+        }
+    }
+
+    /// Returns the journal's path if anything has been recorded
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|_| self.path.as_path())
+    }
+
+    pub fn record(&mut self, action: Action, old_path: &Path, new_path: Option<&Path>) {
+        if !self.enabled {
+            return;
+        }
+
+        let entry = JournalEntry {
+            action,
+            old_path: old_path.to_path_buf(),
+            new_path: new_path.map(|p| p.to_path_buf()),
+            timestamp: now(),
+        };
+
+        if let Err(err) = self.write(&entry) {
+            eprintln!(
+                "Couldn't write journal \"{}\": {}",
+                self.path.to_string_lossy(),
+                err
+            );
+            // don't report the same error for every entry
+            self.enabled = false;
+        }
+    }
+
+    fn write(&mut self, entry: &JournalEntry) -> std::io::Result<()> {
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            self.file = Some(self.create_file()?);
+        }
+
+        if let Some(file) = &mut self.file {
+            // write each entry right away so an aborted run is recorded, too
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn create_file(&mut self) -> std::io::Result<File> {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let first_path = self.path.clone();
+        let mut n = 1;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path)
+            {
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && self.numbered => {
+                    n += 1;
+                    self.path = first_path.with_file_name(format!("{}-{}.jsonl", stem, n));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Reads all entries from a journal file
+pub fn read_journal(path: &Path) -> std::io::Result<Vec<JournalEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Reverts the entries of a journal, newest first, checking each file is still
+/// where the journal left it
+pub fn undo(journal_path: &Path, config: &Config) {
+    let entries = match read_journal(journal_path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!(
+                "Couldn't read journal \"{}\": {}",
+                journal_path.to_string_lossy(),
+                err
+            );
+            return;
+        }
+    };
+
+    // in dry-run mode, nothing is renamed back, so we need to remember where
+    // the files would be to check them
+    let mut simulation = Simulation::default();
+
+    for entry in entries.iter().rev() {
+        match (entry.action, &entry.new_path) {
+            (Action::Rename, Some(new_path)) | (Action::Trash, Some(new_path)) => {
+                if undo_rename(&entry.old_path, new_path, &simulation, config) && config.dry_run {
+                    simulation.rename(new_path, &entry.old_path);
+                }
+            }
+            (Action::CreateDirectory, _) => {
+                println!(
+                    "Removing directory \"{}\"",
+                    entry.old_path.to_string_lossy()
+                );
+                if !config.dry_run {
+                    // this fails for directories that aren't empty, which is what we want
+                    if let Err(err) = fs::remove_dir(&entry.old_path) {
+                        eprintln!(
+                            "Couldn't remove directory \"{}\": {}",
+                            entry.old_path.to_string_lossy(),
+                            err
+                        );
+                    }
+                }
+            }
+            (Action::RemoveDirectory, _) => {
+                println!(
+                    "Creating directory \"{}\"",
+                    entry.old_path.to_string_lossy()
+                );
+                if !config.dry_run {
+                    if let Err(err) = fs::create_dir_all(&entry.old_path) {
+                        eprintln!(
+                            "Couldn't create directory \"{}\": {}",
+                            entry.old_path.to_string_lossy(),
+                            err
+                        );
+                    }
+                }
+            }
+            (Action::Remove, _) | (Action::Rename, None) | (Action::Trash, None) => eprintln!(
+                "Cannot restore \"{}\": it has been removed",
+                entry.old_path.to_string_lossy()
+            ),
+            (Action::WriteTags, _) => eprintln!(
+                "Cannot restore the tags of \"{}\": the old tags haven't been kept",
+                entry.old_path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// Where the files would be if the renames of a dry run had been done
+#[derive(Default)]
+struct Simulation {
+    /// The paths files would have been renamed to and where they really are,
+    /// or None for the paths they would have been renamed from
+    renames: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+impl Simulation {
+    /// Returns where a file would be found on disk, or None if it would have been renamed
+    fn real_path(&self, path: &Path) -> Option<PathBuf> {
+        for (renamed_path, real_path) in self.renames.iter().rev() {
+            if let Ok(rest) = path.strip_prefix(renamed_path) {
+                // joining an empty path would append a slash
+                return match real_path {
+                    Some(real_path) if !rest.as_os_str().is_empty() => Some(real_path.join(rest)),
+                    real_path => real_path.clone(),
+                };
+            }
+        }
+        Some(path.to_path_buf())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.real_path(path).is_some_and(|path| path.exists())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) {
+        let real_path = self.real_path(from);
+        self.renames.push((from.to_path_buf(), None));
+        self.renames.push((to.to_path_buf(), real_path));
+    }
+}
+
+/// Renames a file back, returning whether it has been (or, in dry-run mode, would have been) renamed
+fn undo_rename(old_path: &Path, new_path: &Path, simulation: &Simulation, config: &Config) -> bool {
+    if !simulation.exists(new_path) {
+        eprintln!(
+            "Cannot undo renaming \"{}\": \"{}\" doesn't exist anymore",
+            old_path.to_string_lossy(),
+            new_path.to_string_lossy()
+        );
+        return false;
+    }
+    if simulation.exists(old_path) {
+        eprintln!(
+            "Cannot undo renaming \"{}\": it exists again",
+            old_path.to_string_lossy()
+        );
+        return false;
+    }
+
+    println!(
+        "Renaming \"{}\" back to \"{}\"",
+        new_path.to_string_lossy(),
+        old_path.to_string_lossy()
+    );
+    if !config.dry_run {
+        if let Some(dir) = old_path.parent() {
+            if let Err(err) = fs::create_dir_all(dir) {
+                eprintln!(
+                    "Couldn't create directory \"{}\": {}",
+                    dir.to_string_lossy(),
+                    err
+                );
+                return false;
+            }
+        }
+        if let Err(err) = util::move_file_or_directory(new_path, old_path) {
+            eprintln!("Error renaming \"{}\": {}", new_path.to_string_lossy(), err);
+            return false;
+        }
+    }
+    true
+}
+
+fn default_journal_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match home {
+        Some(home) => PathBuf::from(home).join(".mp3rename"),
+        None => PathBuf::from("."),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_record_and_undo() {
+        let temp_dir = TempDir::new("journal");
+        let dir = temp_dir.path();
+        let old_path = dir.join("old.mp3");
+        let new_path = dir.join("new.mp3");
+        fs::write(&old_path, "foo").unwrap();
+
+        let config = Config {
+            journal: Some(dir.join("journal.jsonl")),
+            ..Config::default()
+        };
+        let mut journal = Journal::new(&config);
+        assert_eq!(journal.path(), None);
+
+        fs::rename(&old_path, &new_path).unwrap();
+        journal.record(Action::Rename, &old_path, Some(&new_path));
+        assert_eq!(journal.path(), Some(dir.join("journal.jsonl").as_path()));
+
+        let entries = read_journal(&dir.join("journal.jsonl")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, Action::Rename);
+        assert_eq!(entries[0].old_path, old_path);
+        assert_eq!(entries[0].new_path, Some(new_path.clone()));
+
+        undo(&dir.join("journal.jsonl"), &config);
+        assert!(old_path.exists());
+        assert!(!new_path.exists());
+    }
+
+    #[test]
+    fn test_runs_never_share_a_journal() {
+        let temp_dir = TempDir::new("journals");
+        let dir = temp_dir.path();
+        let path = dir.join("journal-1718000000.jsonl");
+        let journal = |numbered| Journal {
+            path: path.clone(),
+            numbered,
+            file: None,
+            enabled: true,
+        };
+
+        // two runs in the same second
+        let mut first = journal(true);
+        first.record(Action::Remove, Path::new("/foo.jpg"), None);
+        let mut second = journal(true);
+        second.record(Action::Remove, Path::new("/bar.jpg"), None);
+        assert_eq!(first.path(), Some(path.as_path()));
+        assert_eq!(
+            second.path(),
+            Some(dir.join("journal-1718000000-2.jsonl").as_path())
+        );
+        assert_eq!(read_journal(&path).unwrap().len(), 1);
+
+        // a journal given by the user is never appended to
+        let mut third = journal(false);
+        third.record(Action::Remove, Path::new("/baz.jpg"), None);
+        assert_eq!(third.path(), None);
+        assert_eq!(read_journal(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dry_run_records_nothing() {
+        let config = Config {
+            dry_run: true,
+            journal: Some(PathBuf::from("/nonexistent/journal.jsonl")),
+            ..Config::default()
+        };
+        let mut journal = Journal::new(&config);
+        journal.record(Action::Remove, Path::new("/foo.jpg"), None);
+        assert_eq!(journal.path(), None);
+    }
+}
@@ -1,20 +1,64 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::config::Config;
+use crate::conflict::{ConflictPolicy, Rename};
+use crate::interactive::Decision;
+use crate::journal::{Action, Journal};
 use crate::music_file::MusicFile;
-use crate::ordinary_file::OrdinaryFile;
+use crate::ordinary_file::{Disposition, OrdinaryFile};
+use crate::prune::Pruner;
+use crate::rename_plan::{PlannedAction, RenamePlan};
+use crate::report::{Fingerprint, RecordAction, Report};
+use crate::tag_reader::RawTags;
+use crate::template::Placeholder;
+
+/// Prints a progress message unless a machine-readable output format has been chosen
+macro_rules! progress {
+    ($config:expr, $($arg:tt)*) => {
+        if $config.output_format == OutputFormat::Text {
+            println!($($arg)*);
+        }
+    };
+}
 
 pub mod config;
+mod conflict;
+mod extensions;
+mod interactive;
+mod journal;
 mod music_file;
 mod music_metadata;
+mod normalize;
 mod ordinary_file;
+mod path_length;
+mod path_pattern;
+mod prune;
+mod rename_plan;
+mod report;
+mod sanitize;
+mod tag_reader;
+mod tag_writer;
+mod template;
+#[cfg(test)]
+mod test_util;
+mod transliterate;
+mod trash;
 mod util;
 
+pub use crate::report::OutputFormat;
+
 pub fn rename_music_files(config: &Config) {
     let all_files_and_directories = util::get_list_of_dirs(config);
+    let mut journal = Journal::new(config);
+    let mut report = Report::new(config.output_format);
+    let mut pruner = Pruner::default();
+
+    if let (Some(trash_dir), Some(days)) = (&config.trash_dir, config.purge_trash_older_than) {
+        trash::purge(trash_dir, days, config, &mut journal, &mut report);
+    }
 
     // iterate over directories containing at least one music file
     for dir in all_files_and_directories {
@@ -23,45 +67,161 @@ pub fn rename_music_files(config: &Config) {
                 let (music, others): (Vec<fs::DirEntry>, Vec<fs::DirEntry>) = readdir
                     .filter(|dir_entry| dir_entry.as_ref().unwrap().path().is_file())
                     .map(|dir_entry| dir_entry.unwrap())
-                    .partition(util::is_music_file);
+                    .partition(|dir_entry| util::is_music_file(dir_entry, &config.extensions));
 
                 // only use directories containing music files
                 if !music.is_empty() {
                     let mut music_files: Vec<MusicFile> = music
                         .into_iter()
-                        .map(MusicFile::new)
+                        .map(|dir_entry| MusicFile::new(dir_entry, config))
                         .filter(|music_file| music_file.music_metadata.is_some())
                         .collect();
                     // by now we can be sure all music_files *have* metadata, else we would have filtered them out above
                     music_files.sort_by(MusicFile::sort_func);
 
-                    let ordinary_files: Vec<OrdinaryFile> =
-                        others.into_iter().map(OrdinaryFile::new).collect();
+                    let ordinary_files: Vec<OrdinaryFile> = others
+                        .into_iter()
+                        .map(|dir_entry| OrdinaryFile::new(dir_entry, config))
+                        .collect();
 
-                    handle_directory(dir, music_files, ordinary_files, config);
+                    let go_on = handle_directory(
+                        dir.clone(),
+                        music_files,
+                        ordinary_files,
+                        config,
+                        &mut journal,
+                        &mut report,
+                        &mut pruner,
+                    );
+                    if !go_on {
+                        break;
+                    }
                 }
             }
+
+            // the directories below have been visited before, so they are pruned already
+            if config.prune_empty_dirs && dir.depth() > 0 {
+                prune_directory(dir.path(), config, &mut journal, &mut report, &mut pruner);
+            }
         }
     }
+
+    if let Some(journal_path) = journal.path() {
+        progress!(config, "==============");
+        progress!(
+            config,
+            "Journal written to \"{}\"",
+            journal_path.to_string_lossy()
+        );
+    }
+
+    if let Err(err) = report.write(&mut std::io::stdout(), config.output_format) {
+        eprintln!("Couldn't write the report: {}", err);
+    }
 }
 
+/// Reverts the changes recorded in a journal
+pub fn undo(journal_path: &Path, config: &Config) {
+    journal::undo(journal_path, config);
+}
+
+/// Renames the music files of a directory. Returns false if the user has quit in interactive
+/// mode, so the remaining directories are left alone.
 fn handle_directory(
     dir_entry: walkdir::DirEntry,
     music_files: Vec<MusicFile>,
     ordinary_files: Vec<OrdinaryFile>,
     config: &Config,
-) {
-    println!("==============");
-    println!(
+    journal: &mut Journal,
+    report: &mut Report,
+    pruner: &mut Pruner,
+) -> bool {
+    progress!(config, "==============");
+    progress!(
+        config,
         "Entering directory \"{}\"",
         dir_entry.path().to_string_lossy()
     );
+    report.enter_directory(dir_entry.path());
 
-    let same_artist = music_file::same_artists(&music_files);
+    // the tracks of a compilation keep their artists even if they happen to be the same
+    let same_artist =
+        music_file::same_artists(&music_files) && !music_file::is_compilation(&music_files);
     if config.verbose {
-        println!("Same artist: {}", same_artist);
+        progress!(config, "Same artist: {}", same_artist);
     }
     let same_album_title = music_file::same_album_title(&music_files);
+    let directory_name = match &config.dir_template {
+        None => same_album_title,
+        Some(template) => match music_file::directory_name(&music_files, template, config) {
+            Ok(name) => Some(name),
+            Err(placeholder) => {
+                report.warn(format!(
+                    "Placeholder {{{}}} is not the same for all music files in \"{}\", falling back to the album title",
+                    placeholder.name(),
+                    dir_entry.path().to_string_lossy()
+                ));
+                same_album_title
+            }
+        },
+    };
+
+    // in library mode, the files are moved to a directory built from their tags
+    let mut target_dir = match &config.library_root {
+        None => dir_entry.path().to_path_buf(),
+        Some(library_root) => {
+            match music_file::library_directory(library_root, &music_files, config) {
+                Ok(library_dir) => library_dir,
+                Err(placeholder) => {
+                    report.warn(format!(
+                        "Cannot move \"{}\" to the library: placeholder {{{}}} is not the same for all music files",
+                        dir_entry.path().to_string_lossy(),
+                        placeholder.name()
+                    ));
+                    report.skip(dir_entry.path());
+                    return true;
+                }
+            }
+        }
+    };
+    // the directory is renamed unless the files are moved to the library
+    let mut new_directory_name = match &directory_name {
+        Some(directory_name) if config.rename_directory && config.library_root.is_none() => {
+            let parent_dir = dir_entry.path().parent().unwrap_or_else(|| {
+                panic!(
+                    "Cannot retrieve parent directory of {}",
+                    dir_entry.path().to_string_lossy()
+                )
+            });
+            new_path(dir_entry.path(), parent_dir, config, directory_name)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        }
+        _ => None,
+    };
+
+    // the tags changed by the normalization are written back once the directory is accepted
+    let tag_changes: Vec<(PathBuf, RawTags, Vec<Placeholder>)> = if config.write_tags {
+        music_files
+            .iter()
+            .filter_map(|m| {
+                let metadata = m.music_metadata.as_ref()?;
+                (!metadata.normalized.is_empty()).then(|| {
+                    (
+                        m.dir_entry.path(),
+                        metadata.raw_tags(),
+                        metadata.normalized.clone(),
+                    )
+                })
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // lyrics, cue sheets and the like are renamed along with their music files
+    let music_paths: Vec<PathBuf> = music_files.iter().map(|m| m.dir_entry.path()).collect();
+    let sidecars = ordinary_file::find_sidecars(&ordinary_files, &music_paths);
 
     // partition music files by an Option of their disk number to be able to
     // zero-pad the track numbers individually per *disk* instead of per *directory*
@@ -70,7 +230,7 @@ fn handle_directory(
         if let Some(music_metadata) = &music_file.music_metadata {
             let vec = music_files_by_disk_number_map
                 .entry(music_metadata.disk_number)
-                .or_insert_with(Vec::new);
+                .or_default();
             vec.push(music_file);
         }
     }
@@ -84,11 +244,11 @@ fn handle_directory(
     // Getting keys out of HashMaps is unstable. To always produce the same output,
     // we need to add stability by sorting the keys and producing the output according
     // to this order.
-    let mut sorted_keys: Vec<&Option<u16>> =
-        music_files_by_disk_number_map.keys().into_iter().collect();
+    let mut sorted_keys: Vec<&Option<u16>> = music_files_by_disk_number_map.keys().collect();
     sorted_keys.sort_by(MusicFile::sort_by_disk_number);
 
-    // rename music files
+    // plan renaming the music files
+    let mut renames: Vec<Rename> = Vec::new();
     for disk_number in sorted_keys {
         if let Some(music_files_by_disk_number) = music_files_by_disk_number_map.get(disk_number) {
             for music_file in music_files_by_disk_number {
@@ -100,74 +260,666 @@ fn handle_directory(
                 ) {
                     Some(canonical_name) => {
                         if config.verbose {
-                            println!("Canonical name: {}", canonical_name);
+                            progress!(config, "Canonical name: {}", canonical_name);
+                        }
+                        let old_path = music_file.dir_entry.path();
+                        report.add_tags(&old_path, music_file.tags());
+                        if let Some(metadata) = &music_file.music_metadata {
+                            if !metadata.inferred.is_empty() {
+                                report.warn_about(
+                                    &old_path,
+                                    format!(
+                                        "Inferred {} of \"{}\" from its path",
+                                        music_metadata::field_names(&metadata.inferred),
+                                        old_path.to_string_lossy()
+                                    ),
+                                );
+                            }
                         }
-                        rename_file_or_directory(
-                            music_file.dir_entry.path(),
-                            config,
-                            &canonical_name,
-                        )
+                        let mut new_path =
+                            new_path(&old_path, &target_dir, config, &canonical_name);
+                        // leave room for the names of the sidecars
+                        let excess = ordinary_file::sidecar_excess(&sidecars, &old_path, config);
+                        if config.shorten_names && excess > 0 {
+                            let name = new_path.file_name().unwrap_or_default().to_string_lossy();
+                            let max_len = (config.name_length as usize).saturating_sub(excess);
+                            new_path.set_file_name(util::shorten_name(
+                                &old_path, &name, max_len, config,
+                            ));
+                        }
+                        renames.push(Rename::new(old_path, new_path));
                     }
-                    None => eprintln!("Couldn't retrieve canonical name"),
+                    None => report.warn_about(
+                        &music_file.dir_entry.path(),
+                        String::from("Couldn't retrieve canonical name"),
+                    ),
+                }
+            }
+        }
+    }
+
+    // rename the cover along with the music files, and carry the other files to the library
+    let ordinary_renames =
+        ordinary_file::ordinary_renames(&ordinary_files, &sidecars, &target_dir, config);
+    renames.extend(ordinary_renames);
+
+    if config.max_path_length > 0 {
+        path_length::fit_path_length(
+            &mut target_dir,
+            &mut new_directory_name,
+            &mut renames,
+            &sidecars,
+            config,
+        );
+    }
+    renames.retain(|rename| !util::is_same_path(&rename.old_path, &rename.new_path, config));
+
+    // check all names at once before renaming anything
+    let renames = match resolve_conflicts(renames, config, &dir_entry, report) {
+        Some(renames) => renames,
+        None => return true,
+    };
+
+    if let Some(directory_name) = directory_name {
+        if config.verbose {
+            progress!(config, "Directory name: {}", directory_name);
+        }
+    } else if config.verbose {
+        progress!(config, "Multiple album names.")
+    }
+
+    // rename the directory, unless the files have been moved to the library
+    let mut directory_renames = Vec::new();
+    if let Some(new_directory_name) = new_directory_name {
+        let old_path = dir_entry.path().to_path_buf();
+        let new_path = old_path.with_file_name(new_directory_name);
+        if !util::is_same_path(&old_path, &new_path, config) {
+            if let Some(renames) = resolve_conflicts(
+                vec![Rename::new(old_path, new_path)],
+                config,
+                &dir_entry,
+                report,
+            ) {
+                directory_renames = renames;
+            }
+        }
+    }
+
+    for file in &ordinary_files {
+        if file.disposition == Disposition::Unknown {
+            let path = file.dir_entry.path();
+            report.warn_about(
+                &path,
+                format!(
+                    "Not removing \"{}\": it matches neither a --keep nor a --delete pattern",
+                    path.to_string_lossy()
+                ),
+            );
+            if config.library_root.is_none() {
+                report.skip(&path);
+            }
+        }
+    }
+
+    let (renames, directory_renames) = if config.interactive {
+        let mut all_renames = renames;
+        all_renames.extend(directory_renames);
+        // everything else the plan would do with these renames, with the sidecars' names
+        // not yet checked for conflicts
+        let other_actions = |renames: &[Rename]| {
+            let sidecar_renames = ordinary_file::sidecar_renames(&sidecars, renames, config);
+            plan_directory(
+                &target_dir,
+                &tag_changes,
+                sidecar_renames,
+                &ordinary_files,
+                Vec::new(),
+                config,
+            )
+            .actions()
+            .to_vec()
+        };
+        match confirm(dir_entry.path(), all_renames, other_actions, config) {
+            Decision::Accept(all_renames) => all_renames
+                .into_iter()
+                .partition(|rename| rename.old_path != dir_entry.path()),
+            Decision::Skip => {
+                report.skip(dir_entry.path());
+                return true;
+            }
+            Decision::Quit => {
+                report.skip(dir_entry.path());
+                return false;
+            }
+        }
+    } else {
+        (renames, directory_renames)
+    };
+
+    // the sidecars get the final names of their music files, which may have been given
+    // a suffix or edited, and are checked for conflicts like all other files
+    let sidecar_renames = ordinary_file::sidecar_renames(&sidecars, &renames, config);
+    let renames = if sidecar_renames.is_empty() {
+        renames
+    } else {
+        let mut all_renames = renames;
+        all_renames.extend(sidecar_renames);
+        match resolve_conflicts(all_renames, config, &dir_entry, report) {
+            Some(renames) => renames,
+            None => return true,
+        }
+    };
+
+    let plan = plan_directory(
+        &target_dir,
+        &tag_changes,
+        renames,
+        &ordinary_files,
+        directory_renames,
+        config,
+    );
+    execute_plan(&plan, config, journal, report, pruner);
+    true
+}
+
+/// Plans the changes to a directory in the order they have to be done: creating the
+/// directory the files are moved to, writing the tags, renaming the files, removing the
+/// non-music files, and renaming the directory itself
+fn plan_directory(
+    target_dir: &Path,
+    tag_changes: &[(PathBuf, RawTags, Vec<Placeholder>)],
+    renames: Vec<Rename>,
+    ordinary_files: &[OrdinaryFile],
+    directory_renames: Vec<Rename>,
+    config: &Config,
+) -> RenamePlan {
+    let mut plan = RenamePlan::new();
+    let missing_dirs: Vec<&Path> = target_dir
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .collect();
+    for dir in missing_dirs.iter().rev() {
+        plan.create_directory(dir);
+    }
+    for (path, tags, fields) in tag_changes {
+        plan.write_tags(path, tags.clone(), fields.clone());
+    }
+    plan.add_renames(renames);
+
+    // remove ordinary files, or move them to the trash directory
+    for file in ordinary_files {
+        if file.disposition == Disposition::Remove {
+            plan_removal(&mut plan, &file.dir_entry.path(), config);
+        }
+    }
+
+    plan.add_renames(directory_renames);
+    plan
+}
+
+/// Asks the user what to do with the renames of a directory and the other actions planned
+/// along with them. The questions are printed to stdout, unless it's reserved for
+/// machine-readable output.
+fn confirm(
+    dir: &Path,
+    renames: Vec<Rename>,
+    other_actions: impl Fn(&[Rename]) -> Vec<PlannedAction>,
+    config: &Config,
+) -> Decision {
+    let stdin = std::io::stdin();
+    let result = if config.output_format == OutputFormat::Text {
+        interactive::confirm(
+            dir,
+            renames,
+            other_actions,
+            &mut stdin.lock(),
+            &mut std::io::stdout(),
+        )
+    } else {
+        interactive::confirm(
+            dir,
+            renames,
+            other_actions,
+            &mut stdin.lock(),
+            &mut std::io::stderr(),
+        )
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("Couldn't ask what to do: {}", err);
+        Decision::Quit
+    })
+}
+
+/// Removes a file, or moves it to the trash directory if there is one
+fn plan_removal(plan: &mut RenamePlan, path: &Path, config: &Config) {
+    match &config.trash_dir {
+        Some(trash_dir) => plan.trash(path, &trash::trash_path(trash_dir, path, config)),
+        None => plan.remove(path),
+    }
+}
+
+/// Removes a directory the run has left empty, along with any junk files left in it
+fn prune_directory(
+    dir: &Path,
+    config: &Config,
+    journal: &mut Journal,
+    report: &mut Report,
+    pruner: &mut Pruner,
+) {
+    let junk = match pruner.junk_in_empty_dir(dir, config) {
+        Some(junk) => junk,
+        None => return,
+    };
+
+    let mut plan = RenamePlan::new();
+    for path in &junk {
+        plan_removal(&mut plan, path, config);
+    }
+    plan.remove_directory(dir);
+
+    report.enter_directory(dir);
+    execute_plan(&plan, config, journal, report, pruner);
+}
+
+/// Carries out the plan's actions in order. In dry-run mode, they are only printed.
+fn execute_plan(
+    plan: &RenamePlan,
+    config: &Config,
+    journal: &mut Journal,
+    report: &mut Report,
+    pruner: &mut Pruner,
+) {
+    for planned_action in plan.actions() {
+        report.record(planned_action);
+        if execute_action(planned_action, config, journal) {
+            pruner.note(planned_action);
+        } else if planned_action.action == Action::CreateDirectory {
+            // nothing can be moved there
+            return;
+        }
+    }
+}
+
+/// Carries out a single action, returning whether it succeeded
+fn execute_action(planned_action: &PlannedAction, config: &Config, journal: &mut Journal) -> bool {
+    let path = &planned_action.old_path;
+    match (planned_action.action, &planned_action.new_path) {
+        (Action::CreateDirectory, _) => {
+            progress!(config, "Creating directory \"{}\"", path.to_string_lossy());
+            if !config.dry_run {
+                if let Err(err) = fs::create_dir(path) {
+                    eprintln!(
+                        "Couldn't create directory \"{}\": {}",
+                        path.to_string_lossy(),
+                        err
+                    );
+                    return false;
+                }
+                journal.record(Action::CreateDirectory, path, None);
+            }
+            true
+        }
+        (Action::Rename, Some(new_path)) => {
+            move_file_or_directory(path.clone(), new_path.clone(), config, journal)
+        }
+        (Action::RemoveDirectory, _) => {
+            progress!(
+                config,
+                "Removing empty directory \"{}\"",
+                path.to_string_lossy()
+            );
+            if !config.dry_run {
+                // this fails for directories that aren't empty, which is what we want
+                if let Err(err) = fs::remove_dir(path) {
+                    eprintln!(
+                        "Couldn't remove directory \"{}\": {}",
+                        path.to_string_lossy(),
+                        err
+                    );
+                    return false;
                 }
+                journal.record(Action::RemoveDirectory, path, None);
             }
+            true
+        }
+        (Action::Trash, Some(trash_path)) => {
+            progress!(
+                config,
+                "Moving {} to {}",
+                path.to_string_lossy(),
+                trash_path.to_string_lossy()
+            );
+            if !config.dry_run {
+                let trash_dir = config
+                    .trash_dir
+                    .as_deref()
+                    .or(trash_path.parent())
+                    .unwrap_or(trash_path);
+                if let Err(err) = trash::move_to_trash(path, trash_path, trash_dir) {
+                    eprintln!(
+                        "Couldn't move {} to the trash: {}",
+                        path.to_string_lossy(),
+                        err
+                    );
+                    return false;
+                }
+                journal.record(Action::Trash, path, Some(trash_path));
+            }
+            true
+        }
+        (Action::WriteTags, _) => {
+            let changes = match &planned_action.tags {
+                Some(changes) => changes,
+                None => return false,
+            };
+            progress!(
+                config,
+                "Writing normalized tags to \"{}\": {}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                changes
+            );
+            if !config.dry_run {
+                if let Err(err) = tag_writer::write_tags(path, &changes.tags, &changes.fields) {
+                    eprintln!(
+                        "Couldn't write the tags of \"{}\": {}",
+                        path.to_string_lossy(),
+                        err
+                    );
+                    return false;
+                }
+                journal.record(Action::WriteTags, path, None);
+            }
+            true
+        }
+        (Action::Remove, _) | (Action::Rename, None) | (Action::Trash, None) => {
+            progress!(config, "Removing {}", path.to_string_lossy());
+            if !config.dry_run {
+                if let Err(err) = fs::remove_file(path) {
+                    eprintln!("Couldn't remove {}: {}", path.to_string_lossy(), err);
+                    return false;
+                }
+                journal.record(Action::Remove, path, None);
+            }
+            true
         }
     }
+}
+
+/// Writes the tags found in the paths of the music files into the files, the reverse of
+/// renaming. In a dry run, the new tags are only printed.
+pub fn tag_from_name(config: &Config) {
+    for dir in util::get_list_of_dirs(config) {
+        let mut music_files: Vec<PathBuf> = match fs::read_dir(dir.path()) {
+            Ok(readdir) => readdir
+                .filter_map(|dir_entry| dir_entry.ok())
+                .filter(|dir_entry| dir_entry.path().is_file())
+                .filter(|dir_entry| util::is_music_file(dir_entry, &config.extensions))
+                .map(|dir_entry| dir_entry.path())
+                .collect(),
+            Err(_) => continue,
+        };
+        if music_files.is_empty() {
+            continue;
+        }
+        music_files.sort();
+
+        progress!(config, "==============");
+        progress!(
+            config,
+            "Entering directory \"{}\"",
+            dir.path().to_string_lossy()
+        );
+
+        for path in music_files {
+            let captures = match config
+                .path_patterns
+                .iter()
+                .find_map(|pattern| pattern.captures(&path))
+            {
+                Some(captures) => captures,
+                None => {
+                    eprintln!(
+                        "Leaving \"{}\" alone: no path pattern matches",
+                        path.to_string_lossy()
+                    );
+                    continue;
+                }
+            };
+
+            // untagged files get their first tags
+            let mut tags = tag_reader::read_tags(&path).unwrap_or_default();
+            let fields = tags.set_from_path(captures, config.overwrite_tags);
+            if fields.is_empty() {
+                continue;
+            }
+
+            let changes: Vec<String> = fields
+                .iter()
+                .map(|field| format!("{}=\"{}\"", field.name(), tags.value(*field)))
+                .collect();
+            progress!(
+                config,
+                "Tagging \"{}\" with {}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                changes.join(", ")
+            );
 
-    // remove ordinary files
-    if !ordinary_files.is_empty() && config.remove_ordinary_files {
-        for file in &ordinary_files {
-            println!("Removing {}", file.dir_entry.path().to_string_lossy());
             if !config.dry_run {
-                if let Err(err) = fs::remove_file(file.dir_entry.path()) {
+                if let Err(err) = tag_writer::write_tags(&path, &tags, &fields) {
                     eprintln!(
-                        "Couldn't remove {}: {}",
-                        file.dir_entry.path().to_string_lossy(),
+                        "Couldn't write the tags of \"{}\": {}",
+                        path.to_string_lossy(),
                         err
                     );
-                };
+                }
             }
         }
     }
+}
 
-    // rename the directory
-    if let Some(album_title) = same_album_title {
-        if config.verbose {
-            println!("Same album title: {}", album_title);
+/// Carries out the actions of a plan written with `--output-format json`, leaving alone
+/// every file that has changed since the plan was made
+pub fn apply(plan_path: &Path, config: &Config) {
+    let records = match report::read_plan(plan_path) {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!(
+                "Couldn't read plan \"{}\": {}",
+                plan_path.to_string_lossy(),
+                err
+            );
+            return;
         }
-        if config.rename_directory {
-            rename_file_or_directory(dir_entry.path().to_path_buf(), config, &album_title)
+    };
+    let mut journal = Journal::new(config);
+    let mut directory = None;
+    let mut failed = false;
+    // the files of the directory that have been moved to a temporary name to break a cycle,
+    // and their original paths
+    let mut temporary_paths: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    for record in &records {
+        if record.action == RecordAction::WriteTags {
+            eprintln!(
+                "Not writing the tags of \"{}\": a plan doesn't keep their values",
+                record.old_path().to_string_lossy()
+            );
+            continue;
         }
-    } else if config.verbose {
-        println!("Multiple album names.")
+        let planned_action = match record.planned_action() {
+            Some(planned_action) => planned_action,
+            None => continue,
+        };
+
+        if directory != Some(&record.directory) {
+            directory = Some(&record.directory);
+            failed = false;
+            temporary_paths.clear();
+            progress!(config, "==============");
+            progress!(
+                config,
+                "Entering directory \"{}\"",
+                record.directory.to_string_lossy()
+            );
+        }
+        if failed {
+            continue;
+        }
+
+        if let Some(fingerprint) = &record.fingerprint {
+            match Fingerprint::new(&planned_action.old_path) {
+                Ok(current) if current == *fingerprint => {}
+                Ok(_) => {
+                    eprintln!(
+                        "Leaving \"{}\" alone: it has changed since the plan was made",
+                        planned_action.old_path.to_string_lossy()
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!(
+                        "Leaving \"{}\" alone: {}",
+                        planned_action.old_path.to_string_lossy(),
+                        err
+                    );
+                    continue;
+                }
+            }
+        }
+
+        if !execute_action(&planned_action, config, &mut journal) {
+            // the later steps may depend on this one
+            eprintln!(
+                "Skipping the remaining steps in \"{}\"",
+                record.directory.to_string_lossy()
+            );
+            restore_temporary_paths(&temporary_paths, config, &mut journal);
+            failed = true;
+            continue;
+        }
+        if let (Action::Rename, Some(new_path)) = (planned_action.action, &planned_action.new_path)
+        {
+            temporary_paths.retain(|(path, _)| *path != planned_action.old_path);
+            if rename_plan::is_temporary_path(new_path) {
+                temporary_paths.push((new_path.clone(), planned_action.old_path.clone()));
+            }
+        }
+    }
+
+    if let Some(journal_path) = journal.path() {
+        progress!(config, "==============");
+        progress!(
+            config,
+            "Journal written to \"{}\"",
+            journal_path.to_string_lossy()
+        );
     }
 }
 
-/// Rename a file or directory name in a path
-fn rename_file_or_directory(old_path: PathBuf, config: &Config, to_name: &str) {
-    let old_name = old_path
-        .file_name()
-        .unwrap_or_else(|| {
-            panic!(
-                "Cannot retrieve name part from {}",
-                old_path.to_string_lossy()
+/// Moves the files left with a temporary name by a failed plan back to their original
+/// paths, or reports them if their original paths have been taken in the meantime
+fn restore_temporary_paths(
+    temporary_paths: &[(PathBuf, PathBuf)],
+    config: &Config,
+    journal: &mut Journal,
+) {
+    for (temporary_path, original_path) in temporary_paths.iter().rev() {
+        if original_path.exists()
+            || !move_file_or_directory(
+                temporary_path.clone(),
+                original_path.clone(),
+                config,
+                journal,
             )
-        })
-        .to_string_lossy();
+        {
+            eprintln!(
+                "\"{}\" has been left with its temporary name \"{}\"",
+                original_path.to_string_lossy(),
+                temporary_path.to_string_lossy()
+            );
+        }
+    }
+}
 
+/// Resolves name conflicts according to the configured policy and reports them.
+/// Returns None if the directory is to be left alone.
+fn resolve_conflicts(
+    renames: Vec<Rename>,
+    config: &Config,
+    dir_entry: &walkdir::DirEntry,
+    report: &mut Report,
+) -> Option<Vec<Rename>> {
+    match conflict::resolve_conflicts(renames, config.on_conflict) {
+        Ok((renames, conflicts)) => {
+            for conflict in conflicts {
+                match config.on_conflict {
+                    ConflictPolicy::Suffix => report.warn_about(
+                        conflict.old_path(),
+                        format!("Conflict: {}, adding a suffix", conflict),
+                    ),
+                    _ => {
+                        report.warn_about(
+                            conflict.old_path(),
+                            format!("Conflict: {}, skipping", conflict),
+                        );
+                        report.skip(conflict.old_path());
+                    }
+                }
+            }
+            Some(renames)
+        }
+        Err(conflicts) => {
+            report.warn(format!(
+                "Leaving \"{}\" alone because of name conflicts:",
+                dir_entry.path().to_string_lossy()
+            ));
+            for conflict in conflicts {
+                report.warn(format!("  {}", conflict));
+            }
+            report.skip(dir_entry.path());
+            None
+        }
+    }
+}
+
+/// Returns the sanitized and possibly shortened path a file or directory is renamed to
+fn new_path(old_path: &Path, target_dir: &Path, config: &Config, to_name: &str) -> PathBuf {
     // sanitize the canonical name *without* extension to catch cases like
     // "Foo....mp3" which should become "Foo.mp3"
-    let (extension, _): (String, usize) = util::get_extension(&old_path);
+    let (extension, _): (String, usize) = util::get_extension(old_path);
     let mut short_name_stem = util::get_name_stem(to_name, &extension); // both parameters use lowercase for the extension
-    short_name_stem = util::sanitize_file_or_directory_name(&short_name_stem);
+    short_name_stem = util::sanitize_file_or_directory_name(
+        &transliterate::transliterate_name(&short_name_stem, config),
+        config,
+    );
 
     // now rebuild the name *with* the extension to be able to shorten the canonical name
     let mut to_name = format!("{}{}", short_name_stem, extension);
     if config.shorten_names {
-        to_name = util::shorten_names(&old_path, &to_name, config);
+        to_name = util::shorten_names(old_path, &to_name, config);
     }
+    to_name = config.unicode_normalization.apply(&to_name);
 
-    let new_path = old_path.with_file_name(OsString::from(to_name));
+    target_dir.join(OsString::from(to_name))
+}
+
+/// Move a file or directory to a new path, which may be in the same directory.
+/// Returns whether it has been moved.
+fn move_file_or_directory(
+    old_path: PathBuf,
+    new_path: PathBuf,
+    config: &Config,
+    journal: &mut Journal,
+) -> bool {
+    let old_name = old_path
+        .file_name()
+        .unwrap_or_else(|| {
+            panic!(
+                "Cannot retrieve name part from {}",
+                old_path.to_string_lossy()
+            )
+        })
+        .to_string_lossy();
     let new_name = &new_path
         .file_name()
         .unwrap_or_else(|| {
@@ -178,15 +930,42 @@ fn rename_file_or_directory(old_path: PathBuf, config: &Config, to_name: &str) {
         })
         .to_string_lossy();
 
-    if old_name.eq(new_name) {
-        return;
+    if old_name.eq(new_name) && old_path.parent() == new_path.parent() {
+        return true;
     }
 
-    println!("Renaming \"{}\" to \"{}\"", old_name, new_name);
+    // never overwrite anything, but allow changing the case on case-insensitive file systems.
+    // The plan has been checked for conflicts, so in a dry run, the new name may only be
+    // taken by a file that would have been renamed before.
+    if !config.dry_run
+        && new_path.exists()
+        && !same_file::is_same_file(&old_path, &new_path).unwrap_or(false)
+    {
+        eprintln!(
+            "Cannot rename \"{}\": \"{}\" already exists",
+            old_path.to_string_lossy(),
+            new_path.to_string_lossy()
+        );
+        return false;
+    }
+
+    if old_path.parent() == new_path.parent() {
+        progress!(config, "Renaming \"{}\" to \"{}\"", old_name, new_name);
+    } else {
+        progress!(
+            config,
+            "Moving \"{}\" to \"{}\"",
+            old_path.to_string_lossy(),
+            new_path.to_string_lossy()
+        );
+    }
 
     if !config.dry_run {
-        if let Err(e) = fs::rename(&old_path, new_path) {
+        if let Err(e) = util::move_file_or_directory(&old_path, &new_path) {
             eprintln!("Error renaming \"{}\": {}", old_name, e);
+            return false;
         }
+        journal.record(Action::Rename, &old_path, Some(&new_path));
     }
+    true
 }
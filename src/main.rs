@@ -1,17 +1,26 @@
-use mp3rename::config::Config;
-use mp3rename::rename_music_files;
+use mp3rename::config::{Command, Config};
+use mp3rename::{apply, rename_music_files, tag_from_name, undo, OutputFormat};
 
 fn main() {
     let config = Config::new();
 
-    if config.dry_run {
+    // keep machine-readable output parseable
+    let text_output = config.output_format == OutputFormat::Text;
+
+    if config.dry_run && text_output {
         println!("*** Dry run mode ***");
     }
 
-    if config.verbose {
+    if config.verbose && text_output {
         println!("==============");
         println!("Configuration:");
         println!("{}", config);
     }
-    rename_music_files(&config);
+
+    match &config.command {
+        Command::Rename => rename_music_files(&config),
+        Command::Undo(journal_path) => undo(journal_path, &config),
+        Command::Apply(plan_path) => apply(plan_path, &config),
+        Command::TagFromName => tag_from_name(&config),
+    }
 }
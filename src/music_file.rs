@@ -1,11 +1,15 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fmt::Formatter;
 use std::fs;
+use std::path::{Path, PathBuf};
 
 use crate::config::Config;
 use crate::music_metadata::MusicMetadata;
+use crate::template::{Placeholder, Template, Value};
+use crate::transliterate;
+use crate::util;
 
 pub struct MusicFile {
     pub dir_entry: fs::DirEntry,
@@ -13,8 +17,11 @@ pub struct MusicFile {
 }
 
 impl MusicFile {
-    pub fn new(dir_entry: fs::DirEntry) -> MusicFile {
-        let music_metadata = MusicMetadata::new(&dir_entry);
+    pub fn new(dir_entry: fs::DirEntry, config: &Config) -> MusicFile {
+        let mut music_metadata = MusicMetadata::new(&dir_entry, &config.path_patterns);
+        if let Some(metadata) = &mut music_metadata {
+            metadata.normalized = config.normalization.normalize(metadata);
+        }
 
         MusicFile {
             dir_entry,
@@ -30,6 +37,21 @@ impl MusicFile {
         number_of_music_files_in_this_disk: usize,
     ) -> Option<String> {
         if let Some(metadata) = &self.music_metadata {
+            let extension = match self.dir_entry.path().extension() {
+                None => String::new(),
+                Some(ext) => format!(".{}", ext.to_string_lossy().to_lowercase()),
+            };
+
+            // number of digits to zero-pad the track number
+            let num_digits = number_of_music_files_in_this_disk.to_string().len();
+
+            if let Some(template) = &config.file_template {
+                let name = template.render(|placeholder| {
+                    self.template_value(placeholder, number_of_digits_for_disc_number, num_digits)
+                });
+                return name.ok().map(|name| format!("{}{}", name, extension));
+            }
+
             let disk_number = match metadata.disk_number {
                 None => String::new(),
                 Some(num) => format!(
@@ -39,8 +61,6 @@ impl MusicFile {
                 ),
             };
 
-            // number of digits to zero-pad the track number
-            let num_digits = number_of_music_files_in_this_disk.to_string().len();
             let track_number = format!("{:0width$}", metadata.track_number, width = num_digits);
 
             let artist =
@@ -50,11 +70,6 @@ impl MusicFile {
                     format!(" {} -", &metadata.artist)
                 };
 
-            let extension = match self.dir_entry.path().extension() {
-                None => String::new(),
-                Some(ext) => format!(".{}", ext.to_string_lossy().to_lowercase()),
-            };
-
             let result = format!(
                 "{}{}{} {}{}",
                 disk_number, track_number, artist, metadata.title, extension
@@ -66,12 +81,110 @@ impl MusicFile {
         None
     }
 
+    /// Returns the value a template placeholder is replaced with. Numbers are zero-padded
+    /// to the given widths unless the template specifies its own width.
+    pub fn template_value(
+        &self,
+        placeholder: Placeholder,
+        disc_number_width: usize,
+        track_number_width: usize,
+    ) -> Option<Value> {
+        let metadata = self.music_metadata.as_ref()?;
+
+        let value = match placeholder {
+            Placeholder::Album => Value::Text(metadata.album.clone()),
+            Placeholder::AlbumArtist => Value::Text(self.album_artist()),
+            Placeholder::Artist => Value::Text(metadata.artist.clone()),
+            Placeholder::Composer => Value::Text(metadata.composer.clone().unwrap_or_default()),
+            Placeholder::Disc => match metadata.disk_number {
+                None => Value::Text(String::new()),
+                Some(num) => Value::Number {
+                    value: num,
+                    width: disc_number_width,
+                },
+            },
+            Placeholder::DiscTotal => match metadata.total_discs {
+                None => Value::Text(String::new()),
+                Some(num) => Value::Number {
+                    value: num,
+                    width: disc_number_width,
+                },
+            },
+            Placeholder::Format => Value::Text(match self.dir_entry.path().extension() {
+                None => String::new(),
+                Some(ext) => ext.to_string_lossy().to_uppercase(),
+            }),
+            Placeholder::Genre => Value::Text(metadata.genre.clone().unwrap_or_default()),
+            Placeholder::Title => Value::Text(metadata.title.clone()),
+            Placeholder::Track => Value::Number {
+                value: metadata.track_number,
+                width: track_number_width,
+            },
+            Placeholder::TrackTotal => match metadata.total_tracks {
+                None => Value::Text(String::new()),
+                Some(num) => Value::Number {
+                    value: num,
+                    width: track_number_width,
+                },
+            },
+            Placeholder::Year => Value::Text(
+                metadata
+                    .year
+                    .map(|year| year.to_string())
+                    .unwrap_or_default(),
+            ),
+        };
+
+        Some(value)
+    }
+
+    /// Returns the album artist or, as most files only have one if it differs, the artist
+    pub fn album_artist(&self) -> String {
+        match &self.music_metadata {
+            None => String::new(),
+            Some(metadata) => metadata
+                .album_artist
+                .clone()
+                .unwrap_or_else(|| metadata.artist.clone()),
+        }
+    }
+
+    /// Returns the tags names are built from
+    pub fn tags(&self) -> BTreeMap<String, String> {
+        let mut tags = BTreeMap::new();
+        if let Some(metadata) = &self.music_metadata {
+            tags.insert(String::from("album"), metadata.album.clone());
+            if let Some(album_artist) = &metadata.album_artist {
+                tags.insert(String::from("albumartist"), album_artist.clone());
+            }
+            if metadata.compilation {
+                tags.insert(String::from("compilation"), String::from("1"));
+            }
+            tags.insert(String::from("artist"), metadata.artist.clone());
+            if let Some(composer) = &metadata.composer {
+                tags.insert(String::from("composer"), composer.clone());
+            }
+            if let Some(disk_number) = metadata.disk_number {
+                tags.insert(String::from("disc"), disk_number.to_string());
+            }
+            if let Some(genre) = &metadata.genre {
+                tags.insert(String::from("genre"), genre.clone());
+            }
+            tags.insert(String::from("title"), metadata.title.clone());
+            tags.insert(String::from("track"), metadata.track_number.to_string());
+            if let Some(year) = metadata.year {
+                tags.insert(String::from("year"), year.to_string());
+            }
+        }
+        tags
+    }
+
     pub fn sort_func(left: &MusicFile, right: &MusicFile) -> Ordering {
         MusicMetadata::sort_func(&left.music_metadata, &right.music_metadata)
     }
 
     pub fn sort_by_disk_number(left: &&Option<u16>, right: &&Option<u16>) -> Ordering {
-        MusicMetadata::sort_by_disk_number_func(*left, *right)
+        MusicMetadata::sort_by_disk_number_func(left, right)
     }
 }
 
@@ -91,6 +204,15 @@ impl fmt::Display for MusicFile {
     }
 }
 
+/// Is any music file marked as part of a compilation? Their tracks keep their artists
+/// in the file names, as the directory is named after the album artist.
+pub fn is_compilation(music_files: &[MusicFile]) -> bool {
+    music_files
+        .iter()
+        .filter_map(|m| m.music_metadata.as_ref())
+        .any(|m| m.compilation)
+}
+
 /// Has the whole directory the same artist for every music file?
 pub fn same_artists(music_files: &[MusicFile]) -> bool {
     let artists: Vec<&String> = music_files
@@ -134,6 +256,49 @@ pub fn same_album_title(music_files: &[MusicFile]) -> Option<String> {
     None
 }
 
+/// Renders the directory name from the tags that are the same for all music files.
+/// Compilations are filed under the configured artist instead of their album artist.
+/// Returns the first placeholder whose value differs between the music files.
+pub fn directory_name(
+    music_files: &[MusicFile],
+    template: &Template,
+    config: &Config,
+) -> Result<String, Placeholder> {
+    template.render(|placeholder| {
+        let mut values = music_files.iter().filter_map(|m| match &m.music_metadata {
+            Some(metadata) if metadata.compilation && placeholder == Placeholder::AlbumArtist => {
+                Some(Value::Text(config.compilation_dir_artist.clone()))
+            }
+            _ => m.template_value(placeholder, 0, 0),
+        });
+        let first_value = values.next()?;
+        if values.all(|value| value == first_value) {
+            Some(first_value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the directory below the library root the music files are moved to.
+/// Each component of the library path is rendered like a directory name.
+pub fn library_directory(
+    library_root: &Path,
+    music_files: &[MusicFile],
+    config: &Config,
+) -> Result<PathBuf, Placeholder> {
+    let mut library_dir = library_root.to_path_buf();
+    for template in &config.library_path {
+        let name = directory_name(music_files, template, config)?;
+        let name = util::sanitize_file_or_directory_name(
+            &transliterate::transliterate_name(&name, config),
+            config,
+        );
+        library_dir.push(config.unicode_normalization.apply(&name));
+    }
+    Ok(library_dir)
+}
+
 pub fn largest_disc_number(music_files: &HashMap<Option<u16>, Vec<MusicFile>>) -> Option<u16> {
     let mut largest: u16 = 0;
 
@@ -159,8 +324,11 @@ mod tests {
     const DEFAULT_TITLE: &str = "Foo de Foo";
 
     fn get_dir_entry() -> fs::DirEntry {
-        let mut readdir = fs::read_dir("testfiles").unwrap();
-        readdir.next().unwrap().unwrap()
+        fs::read_dir("testfiles")
+            .unwrap()
+            .map(|dir_entry| dir_entry.unwrap())
+            .find(|dir_entry| dir_entry.file_name() == "foo.mp3")
+            .unwrap()
     }
 
     fn get_music_metadata() -> MusicMetadata {
@@ -170,6 +338,7 @@ mod tests {
             disk_number: None,
             title: DEFAULT_TITLE.to_string(),
             track_number: 1,
+            ..MusicMetadata::default()
         }
     }
 
@@ -759,4 +928,178 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_canonical_name_with_file_template() {
+        let config = Config {
+            file_template: Some(Template::parse("{disc:02}-{track:02} {title}").unwrap()),
+            ..Config::default()
+        };
+        let music_file = MusicFile {
+            dir_entry: get_dir_entry(),
+            music_metadata: Some(MusicMetadata {
+                disk_number: Some(1),
+                ..get_music_metadata()
+            }),
+        };
+        assert_eq!(
+            music_file.canonical_name(&config, false, 1, 1),
+            Some(format!("01-01 {}.mp3", DEFAULT_TITLE))
+        );
+
+        // without a width, numbers are padded like in the default format
+        let config = Config {
+            file_template: Some(Template::parse("{artist} - {album} - {track} {title}").unwrap()),
+            ..Config::default()
+        };
+        assert_eq!(
+            music_file.canonical_name(&config, false, 1, 10),
+            Some(format!(
+                "{} - {} - 01 {}.mp3",
+                DEFAULT_ARTIST, DEFAULT_ALBUM, DEFAULT_TITLE
+            ))
+        );
+    }
+
+    #[test]
+    fn test_directory_name() {
+        let music_files = vec![
+            MusicFile {
+                dir_entry: get_dir_entry(),
+                music_metadata: Some(get_music_metadata()),
+            },
+            MusicFile {
+                dir_entry: get_dir_entry(),
+                music_metadata: Some(MusicMetadata {
+                    track_number: 2,
+                    title: String::from("Bar"),
+                    ..get_music_metadata()
+                }),
+            },
+        ];
+
+        let config = Config::default();
+        assert_eq!(
+            directory_name(
+                &music_files,
+                &Template::parse("{artist} - {album} [{format}]").unwrap(),
+                &config
+            ),
+            Ok(format!("{} - {} [MP3]", DEFAULT_ARTIST, DEFAULT_ALBUM))
+        );
+        assert_eq!(
+            directory_name(
+                &music_files,
+                &Template::parse("{album} - {title}").unwrap(),
+                &config
+            ),
+            Err(Placeholder::Title)
+        );
+    }
+
+    #[test]
+    fn test_compilation() {
+        let config = Config {
+            compilation_dir_artist: String::from("Various Artists"),
+            ..Config::default()
+        };
+        let template = Template::parse("{albumartist}/{album}").unwrap();
+        let music_files = vec![
+            MusicFile {
+                dir_entry: get_dir_entry(),
+                music_metadata: Some(MusicMetadata {
+                    compilation: true,
+                    ..get_music_metadata()
+                }),
+            },
+            MusicFile {
+                dir_entry: get_dir_entry(),
+                music_metadata: Some(MusicMetadata {
+                    artist: String::from("Bar"),
+                    compilation: true,
+                    track_number: 2,
+                    ..get_music_metadata()
+                }),
+            },
+        ];
+        assert!(is_compilation(&music_files));
+        assert_eq!(
+            directory_name(&music_files, &template, &config),
+            Ok(format!("Various Artists/{}", DEFAULT_ALBUM))
+        );
+
+        // file names keep the real album artist
+        let config = Config {
+            file_template: Some(Template::parse("{albumartist} - {title}").unwrap()),
+            ..config
+        };
+        assert_eq!(
+            music_files[1].canonical_name(&config, false, 1, 2),
+            Some(format!("Bar - {}.mp3", DEFAULT_TITLE))
+        );
+
+        // without the flag, the album artist or else the artist is used
+        let music_files = vec![MusicFile {
+            dir_entry: get_dir_entry(),
+            music_metadata: Some(get_music_metadata()),
+        }];
+        assert!(!is_compilation(&music_files));
+        assert_eq!(
+            directory_name(&music_files, &template, &config),
+            Ok(format!("{}/{}", DEFAULT_ARTIST, DEFAULT_ALBUM))
+        );
+        let music_files = vec![MusicFile {
+            dir_entry: get_dir_entry(),
+            music_metadata: Some(MusicMetadata {
+                album_artist: Some(String::from("Foo Orchestra")),
+                ..get_music_metadata()
+            }),
+        }];
+        assert_eq!(
+            directory_name(&music_files, &template, &config),
+            Ok(format!("Foo Orchestra/{}", DEFAULT_ALBUM))
+        );
+    }
+
+    #[test]
+    fn test_library_directory() {
+        let config = Config {
+            library_path: vec![
+                Template::parse("{artist}").unwrap(),
+                Template::parse("{year} - {album}").unwrap(),
+            ],
+            ..Config::default()
+        };
+        let library_root = Path::new("/music");
+        let music_file = |track_number, album: &str| MusicFile {
+            dir_entry: get_dir_entry(),
+            music_metadata: Some(MusicMetadata {
+                album: album.to_string(),
+                artist: String::from("AC/DC"),
+                track_number,
+                year: Some(1980),
+                ..get_music_metadata()
+            }),
+        };
+
+        // each component is sanitized on its own
+        let music_files = vec![
+            music_file(1, "Back: In Black?"),
+            music_file(2, "Back: In Black?"),
+        ];
+        assert_eq!(
+            library_directory(library_root, &music_files, &config),
+            Ok(PathBuf::from("/music/AC & DC/1980 - Back - In Black"))
+        );
+
+        // the directory is left alone if the tracks don't agree on the path
+        let music_files = vec![
+            music_file(1, "Back in Black"),
+            music_file(2, "Highway to Hell"),
+        ];
+        assert_eq!(
+            library_directory(library_root, &music_files, &config),
+            Err(Placeholder::Album)
+        );
+    }
 }
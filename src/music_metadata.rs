@@ -2,35 +2,77 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Formatter;
 
+use crate::path_pattern::PathPattern;
+use crate::tag_reader;
+use crate::tag_reader::RawTags;
+use crate::template::Placeholder;
+
+#[derive(Default)]
 pub struct MusicMetadata {
     pub album: String,
+    pub album_artist: Option<String>,
     pub artist: String,
+    pub comment: Option<String>,
+    pub compilation: bool,
+    pub composer: Option<String>,
     pub disk_number: Option<u16>,
+    pub genre: Option<String>,
+    /// The fields that were missing in the tags and were taken from the path instead
+    pub inferred: Vec<Placeholder>,
+    /// The fields changed by the normalization
+    pub normalized: Vec<Placeholder>,
     pub title: String,
+    pub total_discs: Option<u16>,
+    pub total_tracks: Option<u16>,
     pub track_number: u16,
+    pub year: Option<i32>,
 }
 
 impl MusicMetadata {
-    pub fn new(music_file: &std::fs::DirEntry) -> Option<MusicMetadata> {
-        let tag = match audiotags::Tag::new().read_from_path(music_file.path()) {
+    /// Reads the tags of a music file. With path patterns, missing tags are inferred from
+    /// the path using the first pattern that matches.
+    pub fn new(
+        music_file: &std::fs::DirEntry,
+        path_patterns: &[PathPattern],
+    ) -> Option<MusicMetadata> {
+        let mut tags = match tag_reader::read_tags(&music_file.path()) {
             Ok(t) => t,
+            // an untagged file may still have all we need in its path
+            Err(_) if !path_patterns.is_empty() => RawTags::default(),
             Err(e) => {
                 eprintln!("{}: {}", music_file.path().to_string_lossy(), e);
                 return None;
             }
         };
+        let inferred = match path_patterns
+            .iter()
+            .find_map(|pattern| pattern.captures(&music_file.path()))
+        {
+            Some(captures) => tags.set_from_path(captures, false),
+            None => Vec::new(),
+        };
 
         // we only accept *complete* metadata
-        if let Some(album) = tag.album_title() {
-            if let Some(artist) = tag.artist() {
-                if let Some(title) = tag.title() {
-                    if let Some(track_number) = tag.track_number() {
+        if let Some(album) = tags.album {
+            if let Some(artist) = tags.artist {
+                if let Some(title) = tags.title {
+                    if let Some(track_number) = tags.track_number {
                         return Some(MusicMetadata {
-                            album: album.to_string(),
-                            artist: artist.to_string(),
-                            disk_number: tag.disc_number(),
-                            title: title.to_string(),
+                            album,
+                            album_artist: tags.album_artist,
+                            artist,
+                            comment: tags.comment,
+                            compilation: tags.compilation,
+                            composer: tags.composer,
+                            disk_number: tags.disc_number,
+                            genre: tags.genre,
+                            inferred,
+                            normalized: Vec::new(),
+                            title,
+                            total_discs: tags.total_discs,
+                            total_tracks: tags.total_tracks,
                             track_number,
+                            year: tags.year,
                         });
                     }
                 }
@@ -38,12 +80,36 @@ impl MusicMetadata {
         }
 
         eprintln!(
-            "Error: Incomplete tags found in {} -- need album, artist, title, and track number.",
-            music_file.path().to_string_lossy()
+            "Error: Incomplete tags found in {} -- need album, artist, title, and track number{}.",
+            music_file.path().to_string_lossy(),
+            if path_patterns.is_empty() {
+                ""
+            } else {
+                ", either in the tags or in the path"
+            }
         );
         None
     }
 
+    /// Returns the tags in the form they are written to the files
+    pub fn raw_tags(&self) -> RawTags {
+        RawTags {
+            album: Some(self.album.clone()),
+            album_artist: self.album_artist.clone(),
+            artist: Some(self.artist.clone()),
+            comment: self.comment.clone(),
+            compilation: self.compilation,
+            composer: self.composer.clone(),
+            disc_number: self.disk_number,
+            genre: self.genre.clone(),
+            title: Some(self.title.clone()),
+            total_discs: self.total_discs,
+            total_tracks: self.total_tracks,
+            track_number: Some(self.track_number),
+            year: self.year,
+        }
+    }
+
     pub fn sort_func(a: &Option<MusicMetadata>, b: &Option<MusicMetadata>) -> Ordering {
         let left = a.as_ref().unwrap_or_else(|| panic!("No tags defined"));
         let right = b.as_ref().unwrap_or_else(|| panic!("No tags defined"));
@@ -79,14 +145,50 @@ impl MusicMetadata {
     }
 }
 
+/// Lists the fields like "track, title"
+pub fn field_names(inferred: &[Placeholder]) -> String {
+    inferred
+        .iter()
+        .map(|p| p.name())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
 impl fmt::Display for MusicMetadata {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "Album:        {}", self.album)?;
+        if let Some(album_artist) = &self.album_artist {
+            writeln!(f, "Album Artist: {}", album_artist)?;
+        }
+        if self.compilation {
+            writeln!(f, "Compilation:  yes")?;
+        }
+        if let Some(year) = self.year {
+            writeln!(f, "Year:         {}", year)?;
+        }
+        if let Some(genre) = &self.genre {
+            writeln!(f, "Genre:        {}", genre)?;
+        }
         if let Some(disk_number) = self.disk_number {
             writeln!(f, "Disk Number:  {}", disk_number)?;
         }
+        if let Some(total_discs) = self.total_discs {
+            writeln!(f, "Total Discs:  {}", total_discs)?;
+        }
         writeln!(f, "Track Number: {}", self.track_number)?;
+        if let Some(total_tracks) = self.total_tracks {
+            writeln!(f, "Total Tracks: {}", total_tracks)?;
+        }
         writeln!(f, "Artist:       {}", self.artist)?;
+        if let Some(composer) = &self.composer {
+            writeln!(f, "Composer:     {}", composer)?;
+        }
+        if let Some(comment) = &self.comment {
+            writeln!(f, "Comment:      {}", comment)?;
+        }
+        if !self.inferred.is_empty() {
+            writeln!(f, "Inferred:     {}", field_names(&self.inferred))?;
+        }
         writeln!(f, "Title:        {}", self.title)
     }
 }
@@ -107,6 +209,7 @@ mod tests {
                 disk_number: None,
                 title: "".to_string(),
                 track_number: 0,
+                ..MusicMetadata::default()
             }),
         );
         MusicMetadata::sort_func(
@@ -116,6 +219,7 @@ mod tests {
                 disk_number: None,
                 title: "".to_string(),
                 track_number: 0,
+                ..MusicMetadata::default()
             }),
             &None,
         );
@@ -131,6 +235,7 @@ mod tests {
                     disk_number: None,
                     title: "".to_string(),
                     track_number: 0,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
@@ -138,6 +243,7 @@ mod tests {
                     disk_number: None,
                     title: "".to_string(),
                     track_number: 0,
+                    ..MusicMetadata::default()
                 }),
             ),
             Ordering::Equal
@@ -153,14 +259,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: Some(1),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
             ),
             Ordering::Greater,
@@ -172,14 +280,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: Some(1),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
             ),
             Ordering::Less,
@@ -191,14 +301,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: Some(1),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: Some(2),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
             ),
             Ordering::Less,
@@ -210,14 +322,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: Some(2),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: Some(1),
                     title: "".to_string(),
-                    track_number: 0
+                    track_number: 0,
+                    ..MusicMetadata::default()
                 }),
             ),
             Ordering::Greater,
@@ -233,14 +347,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 1
+                    track_number: 1,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 2
+                    track_number: 2,
+                    ..MusicMetadata::default()
                 })
             ),
             Ordering::Less
@@ -252,14 +368,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 2
+                    track_number: 2,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 1
+                    track_number: 1,
+                    ..MusicMetadata::default()
                 })
             ),
             Ordering::Greater
@@ -271,14 +389,16 @@ mod tests {
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 1
+                    track_number: 1,
+                    ..MusicMetadata::default()
                 }),
                 &Some(MusicMetadata {
                     album: "".to_string(),
                     artist: "".to_string(),
                     disk_number: None,
                     title: "".to_string(),
-                    track_number: 1
+                    track_number: 1,
+                    ..MusicMetadata::default()
                 })
             ),
             Ordering::Equal
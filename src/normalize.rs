@@ -0,0 +1,185 @@
+use regex::Regex;
+
+use crate::music_metadata::MusicMetadata;
+use crate::template::Placeholder;
+
+/// The words title case keeps lowercase unless they start the text
+pub const DEFAULT_TITLE_CASE_EXCEPTIONS: &str =
+    "a,an,and,as,at,but,by,for,from,in,nor,of,on,or,the,to,vs.,with";
+
+/// How the text tags are cleaned up before the names are built from them.
+/// The steps are applied in the order of the fields.
+#[derive(Debug, Default)]
+pub struct Normalization {
+    /// Regular expressions whose matches are removed, e.g. `\s*\(Remastered \d+\)`
+    pub strip: Vec<Regex>,
+    /// The words title case keeps lowercase, or `None` if titles aren't changed
+    pub title_case: Option<Vec<String>>,
+    /// The spelling "feat.", "ft.", and "featuring" are unified to
+    pub feat: Option<String>,
+    /// Whether leading, trailing, and repeated whitespace is removed
+    pub trim: bool,
+}
+
+impl Normalization {
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for regex in &self.strip {
+            text = regex.replace_all(&text, "").to_string();
+        }
+
+        if let Some(exceptions) = &self.title_case {
+            text = title_case(&text, exceptions);
+        }
+
+        // after title case, which would capitalize the spelling
+        if let Some(feat) = &self.feat {
+            let re = Regex::new(r"(?i)\b(?:feat\.?|ft\.|featuring)(\s+|$)").unwrap();
+            text = re
+                .replace_all(&text, |captures: &regex::Captures| {
+                    format!("{}{}", feat, &captures[1])
+                })
+                .to_string();
+        }
+
+        if self.trim {
+            let re = Regex::new(r"\s+").unwrap();
+            text = re.replace_all(text.trim(), " ").to_string();
+        }
+
+        text
+    }
+
+    /// Normalizes all text tags of a music file. Returns the placeholders whose values changed.
+    pub fn normalize(&self, metadata: &mut MusicMetadata) -> Vec<Placeholder> {
+        let mut changed = Vec::new();
+        let mut normalize = |placeholder: Placeholder, value: &mut String| {
+            let normalized = self.apply(value);
+            if normalized != *value {
+                *value = normalized;
+                changed.push(placeholder);
+            }
+        };
+
+        normalize(Placeholder::Album, &mut metadata.album);
+        if let Some(album_artist) = &mut metadata.album_artist {
+            normalize(Placeholder::AlbumArtist, album_artist);
+        }
+        normalize(Placeholder::Artist, &mut metadata.artist);
+        if let Some(composer) = &mut metadata.composer {
+            normalize(Placeholder::Composer, composer);
+        }
+        if let Some(genre) = &mut metadata.genre {
+            normalize(Placeholder::Genre, genre);
+        }
+        normalize(Placeholder::Title, &mut metadata.title);
+
+        changed
+    }
+}
+
+/// Capitalizes the words written in all caps or all lowercase. Words in mixed case like
+/// "McCartney" and abbreviations like "U.S.A." are kept, and the exceptions are lowercase
+/// unless they start the text.
+fn title_case(text: &str, exceptions: &[String]) -> String {
+    text.split(' ')
+        .enumerate()
+        .map(|(index, word)| {
+            let lowercase = word.to_lowercase();
+            if index > 0 && exceptions.contains(&lowercase) {
+                return lowercase;
+            }
+
+            let letters: Vec<char> = word.chars().filter(|c| c.is_alphabetic()).collect();
+            let all_upper = letters.len() > 1 && letters.iter().all(|c| c.is_uppercase());
+            let all_lower = letters.iter().all(|c| c.is_lowercase());
+            let abbreviation = all_upper && word.contains('.');
+            if letters.is_empty() || abbreviation || !(all_upper || all_lower) {
+                return word.to_string();
+            }
+
+            // capitalize the first letter, keeping leading punctuation like "("
+            let mut capitalized = String::new();
+            let mut first = true;
+            for c in lowercase.chars() {
+                if first && c.is_alphabetic() {
+                    capitalized.extend(c.to_uppercase());
+                    first = false;
+                } else {
+                    capitalized.push(c);
+                }
+            }
+            capitalized
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exceptions() -> Vec<String> {
+        DEFAULT_TITLE_CASE_EXCEPTIONS
+            .split(',')
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_title_case() {
+        assert_eq!(
+            title_case("BACK IN THE U.S.S.R.", &exceptions()),
+            "Back in the U.S.S.R."
+        );
+        assert_eq!(
+            title_case("the fool on the hill (remastered)", &exceptions()),
+            "The Fool on the Hill (Remastered)"
+        );
+        assert_eq!(
+            title_case("Paul McCartney", &exceptions()),
+            "Paul McCartney"
+        );
+        assert_eq!(title_case("ÉTÉ INDIEN", &exceptions()), "Été Indien");
+    }
+
+    #[test]
+    fn test_apply() {
+        let normalization = Normalization {
+            strip: vec![Regex::new(r"\s*\(Remastered \d+\)").unwrap()],
+            title_case: Some(exceptions()),
+            feat: Some(String::from("feat.")),
+            trim: true,
+        };
+        assert_eq!(
+            normalization.apply("  Foo  (Remastered 2011) "),
+            String::from("Foo")
+        );
+        assert_eq!(
+            normalization.apply("FOO ft. Bar Featuring Baz FEAT Qux"),
+            String::from("Foo feat. Bar feat. Baz feat. Qux")
+        );
+        // words merely starting like "feat" are kept
+        assert_eq!(normalization.apply("Feather"), String::from("Feather"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalization = Normalization {
+            trim: true,
+            ..Normalization::default()
+        };
+        let mut metadata = MusicMetadata {
+            album: String::from("Album"),
+            artist: String::from("Artist "),
+            title: String::from(" Title"),
+            ..MusicMetadata::default()
+        };
+        assert_eq!(
+            normalization.normalize(&mut metadata),
+            vec![Placeholder::Artist, Placeholder::Title]
+        );
+        assert_eq!(metadata.title, "Title");
+    }
+}
@@ -1,11 +1,479 @@
+use std::fmt;
+use std::fmt::Formatter;
 use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::conflict::Rename;
+use crate::util;
+
+/// The extensions of the images that can be album covers
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "bmp", "webp"];
+
+/// Words in the names of images that are likely the album cover
+const COVER_WORDS: [&str; 3] = ["cover", "folder", "front"];
+
+/// What happens to a non-music file with `--remove`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Disposition {
+    Keep,
+    Remove,
+    /// Matches neither a keep nor a delete pattern, so it is reported and left alone
+    Unknown,
+}
+
+/// A pattern matched against the names of non-music files: a glob like `"*.jpg"` or
+/// `"folder.*"`, ignoring case, or a regular expression prefixed with `"re:"`
+#[derive(Debug)]
+pub struct FilePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl FilePattern {
+    pub fn parse(source: &str) -> Result<FilePattern, String> {
+        let regex = match source.strip_prefix("re:") {
+            Some(regex) => regex.to_string(),
+            None => glob_to_regex(source),
+        };
+
+        Ok(FilePattern {
+            source: source.to_string(),
+            regex: Regex::new(&regex).map_err(|err| err.to_string())?,
+        })
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl fmt::Display for FilePattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Turns a glob into a regular expression matching whole names: `*` stands for any
+/// characters, `?` for a single one
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("(?i)^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
 
 pub struct OrdinaryFile {
     pub dir_entry: fs::DirEntry,
+    pub disposition: Disposition,
 }
 
 impl OrdinaryFile {
-    pub fn new(dir_entry: fs::DirEntry) -> OrdinaryFile {
-        OrdinaryFile { dir_entry }
+    /// Without keep and delete patterns, `--remove` removes every non-music file. Else only the
+    /// files matching a delete pattern are removed, and keep patterns win over delete patterns.
+    pub fn new(dir_entry: fs::DirEntry, config: &Config) -> OrdinaryFile {
+        let name = dir_entry.file_name().to_string_lossy().to_string();
+        let disposition = if !config.remove_ordinary_files {
+            Disposition::Keep
+        } else if config.keep_patterns.is_empty() && config.delete_patterns.is_empty() {
+            Disposition::Remove
+        } else if config.keep_patterns.iter().any(|p| p.is_match(&name)) {
+            Disposition::Keep
+        } else if config.delete_patterns.iter().any(|p| p.is_match(&name)) {
+            Disposition::Remove
+        } else {
+            Disposition::Unknown
+        };
+
+        OrdinaryFile {
+            dir_entry,
+            disposition,
+        }
+    }
+}
+
+/// A non-music file named after a music file, like lyrics or a cue sheet
+#[derive(Debug, PartialEq)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    /// The music file the sidecar belongs to
+    pub music_path: PathBuf,
+    /// Everything after the music file's name without its extension, like ".en.lrc"
+    pub suffix: String,
+}
+
+/// Finds the sidecars among the non-music files that aren't removed: the files whose names
+/// start with the name of one of the music files without its extension, followed by a dot
+pub fn find_sidecars(ordinary_files: &[OrdinaryFile], music_paths: &[PathBuf]) -> Vec<Sidecar> {
+    ordinary_files
+        .iter()
+        .filter(|file| file.disposition != Disposition::Remove)
+        .filter_map(|file| {
+            let name = file.dir_entry.file_name().to_string_lossy().to_string();
+            let (music_path, suffix) = sidecar_suffix(&name, music_paths)?;
+            Some(Sidecar {
+                path: file.dir_entry.path(),
+                music_path,
+                suffix,
+            })
+        })
+        .collect()
+}
+
+/// Returns the music file a sidecar belongs to and the rest of the sidecar's name
+fn sidecar_suffix(name: &str, music_paths: &[PathBuf]) -> Option<(PathBuf, String)> {
+    music_paths
+        .iter()
+        .filter_map(|music_path| {
+            let stem = music_path.file_stem()?.to_string_lossy();
+            let suffix = name.strip_prefix(stem.as_ref())?;
+            suffix
+                .starts_with('.')
+                .then(|| (stem.len(), music_path.clone(), suffix.to_string()))
+        })
+        // "01 Foo.bar.mp3" fits "01 Foo.bar.lrc" better than "01 Foo.mp3" does
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, music_path, suffix)| (music_path, suffix))
+}
+
+/// Returns how much longer than the music file's name the name of its longest sidecar is.
+/// A shortened music file leaves room for it, so its sidecars fit the same limit.
+pub fn sidecar_excess(sidecars: &[Sidecar], music_path: &Path, config: &Config) -> usize {
+    let unit = config.length_unit;
+    let (extension, _) = util::get_extension(music_path);
+    sidecars
+        .iter()
+        .filter(|sidecar| sidecar.music_path == music_path)
+        .map(|sidecar| {
+            unit.length(&sidecar.suffix)
+                .saturating_sub(unit.length(&extension))
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+/// Plans renaming the sidecars along with their music files, given the music files' final
+/// renames. A sidecar gets its music file's new name without the extension, followed by its
+/// own suffix, and goes to the same directory. The sidecars of music files that aren't renamed
+/// stay where they are, and so do the sidecars already named right.
+pub fn sidecar_renames(
+    sidecars: &[Sidecar],
+    music_renames: &[Rename],
+    config: &Config,
+) -> Vec<Rename> {
+    sidecars
+        .iter()
+        .filter_map(|sidecar| {
+            let rename = music_renames
+                .iter()
+                .find(|rename| rename.old_path == sidecar.music_path)?;
+            let new_stem = rename.new_path.file_stem()?.to_string_lossy();
+            Some(Rename::new(
+                sidecar.path.clone(),
+                rename
+                    .new_path
+                    .with_file_name(format!("{}{}", new_stem, sidecar.suffix)),
+            ))
+        })
+        .filter(|rename| !util::is_same_path(&rename.old_path, &rename.new_path, config))
+        .collect()
+}
+
+/// Plans renaming the other non-music files along with the music files: the album cover
+/// is renamed to `--cover-name`, and in library mode, all files that are neither removed nor
+/// sidecars are carried along, too. The files renamed are shortened to `--limit-length`
+/// like the music files.
+pub fn ordinary_renames(
+    ordinary_files: &[OrdinaryFile],
+    sidecars: &[Sidecar],
+    target_dir: &Path,
+    config: &Config,
+) -> Vec<Rename> {
+    let files: Vec<&OrdinaryFile> = ordinary_files
+        .iter()
+        .filter(|file| file.disposition != Disposition::Remove)
+        // sidecars follow their music files, and images like "01 Foo.jpg" aren't the album cover
+        .filter(|file| {
+            let path = file.dir_entry.path();
+            !sidecars.iter().any(|sidecar| sidecar.path == path)
+        })
+        .collect();
+    let names: Vec<String> = files
+        .iter()
+        .map(|file| file.dir_entry.file_name().to_string_lossy().to_string())
+        .collect();
+
+    let images: Vec<&String> = names.iter().filter(|name| is_image(name)).collect();
+    let cover = match &config.cover_name {
+        Some(_) => find_cover(&images),
+        None => None,
+    };
+
+    let mut renames = Vec::new();
+    for (file, name) in files.iter().zip(&names) {
+        let new_name = match &config.cover_name {
+            Some(cover_name) if cover == Some(name) => cover_file_name(name, cover_name),
+            _ => name.clone(),
+        };
+        if config.library_root.is_none() && new_name == *name {
+            continue;
+        }
+        let new_name = if config.shorten_names {
+            util::shorten_ordinary_name(&new_name, config.name_length as usize, config)
+        } else {
+            new_name
+        };
+        renames.push(Rename::new(
+            file.dir_entry.path(),
+            target_dir.join(new_name),
+        ));
+    }
+    renames
+}
+
+fn is_image(name: &str) -> bool {
+    Path::new(name).extension().is_some_and(|ext| {
+        IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// Returns the album cover among the images: the only image, or else the only one with
+/// a name like "cover", "folder", or "front". If it's not clear, there is no cover.
+fn find_cover<'a>(images: &[&'a String]) -> Option<&'a String> {
+    if images.len() == 1 {
+        return Some(images[0]);
+    }
+
+    let covers: Vec<&String> = images
+        .iter()
+        .filter(|name| {
+            let name = name.to_lowercase();
+            COVER_WORDS.iter().any(|word| name.contains(word))
+        })
+        .copied()
+        .collect();
+    match covers.as_slice() {
+        [cover] => Some(cover),
+        _ => None,
+    }
+}
+
+/// Returns the cover's new name: the cover name without any image extension, followed by
+/// the image's own extension, writing "jpeg" as "jpg"
+fn cover_file_name(name: &str, cover_name: &str) -> String {
+    let cover_stem = if is_image(cover_name) {
+        Path::new(cover_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    } else {
+        cover_name.to_string()
+    };
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "jpeg" => format!("{}.jpg", cover_stem),
+        extension => format!("{}.{}", cover_stem, extension),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sidecar_suffix() {
+        let music_paths = vec![
+            PathBuf::from("/music/01 foo.mp3"),
+            PathBuf::from("/music/01 foo.live.mp3"),
+        ];
+        assert_eq!(
+            sidecar_suffix("01 foo.lrc", &music_paths),
+            Some((music_paths[0].clone(), String::from(".lrc")))
+        );
+        assert_eq!(
+            sidecar_suffix("01 foo.en.lrc", &music_paths),
+            Some((music_paths[0].clone(), String::from(".en.lrc")))
+        );
+        assert_eq!(
+            sidecar_suffix("01 foo.live.cue", &music_paths),
+            Some((music_paths[1].clone(), String::from(".cue")))
+        );
+        assert_eq!(sidecar_suffix("01 foobar.lrc", &music_paths), None);
+        assert_eq!(sidecar_suffix("cover.jpg", &music_paths), None);
+    }
+
+    #[test]
+    fn test_sidecar_renames() {
+        let sidecar = |name: &str, music_name: &str, suffix: &str| Sidecar {
+            path: Path::new("/music").join(name),
+            music_path: Path::new("/music").join(music_name),
+            suffix: suffix.to_string(),
+        };
+        let sidecars = vec![
+            sidecar("01 foo.lrc", "01 foo.mp3", ".lrc"),
+            sidecar("01 foo.en.lrc", "01 foo.mp3", ".en.lrc"),
+            sidecar("02 bar.cue", "02 bar.flac", ".cue"),
+        ];
+        // the second track was given a suffix because of a conflict
+        let music_renames = vec![Rename::new(
+            PathBuf::from("/music/01 foo.mp3"),
+            PathBuf::from("/music/01 The Foos - Foo (2).mp3"),
+        )];
+        let config = Config::default();
+
+        assert_eq!(
+            sidecar_renames(&sidecars, &music_renames, &config),
+            vec![
+                Rename::new(
+                    PathBuf::from("/music/01 foo.lrc"),
+                    PathBuf::from("/music/01 The Foos - Foo (2).lrc")
+                ),
+                Rename::new(
+                    PathBuf::from("/music/01 foo.en.lrc"),
+                    PathBuf::from("/music/01 The Foos - Foo (2).en.lrc")
+                ),
+            ]
+        );
+
+        assert_eq!(
+            sidecar_excess(&sidecars, Path::new("/music/01 foo.mp3"), &config),
+            3
+        );
+        assert_eq!(
+            sidecar_excess(&sidecars, Path::new("/music/02 bar.flac"), &config),
+            0
+        );
+    }
+
+    #[test]
+    fn test_find_cover() {
+        let cover = String::from("Front.jpeg");
+        let back = String::from("back.jpg");
+        assert_eq!(find_cover(&[&back]), Some(&back));
+        assert_eq!(find_cover(&[&back, &cover]), Some(&cover));
+        assert_eq!(
+            find_cover(&[&back, &cover, &String::from("cover.png")]),
+            None
+        );
+        assert_eq!(find_cover(&[]), None);
+
+        assert_eq!(cover_file_name("Front.jpeg", "cover.jpg"), "cover.jpg");
+        assert_eq!(cover_file_name("Front.PNG", "folder"), "folder.png");
+    }
+
+    #[test]
+    fn test_carry_into_library() {
+        let temp_dir = TempDir::new("carry");
+        let dir = temp_dir.path();
+        fs::write(dir.join("a.lrc"), "foo").unwrap();
+        fs::write(dir.join("booklet.pdf"), "foo").unwrap();
+        fs::write(dir.join("Thumbs.db"), "foo").unwrap();
+        let config = Config {
+            delete_patterns: vec![FilePattern::parse("Thumbs.db").unwrap()],
+            library_root: Some(PathBuf::from("/music")),
+            remove_ordinary_files: true,
+            ..Config::default()
+        };
+        let ordinary_files: Vec<OrdinaryFile> = fs::read_dir(dir)
+            .unwrap()
+            .map(|dir_entry| OrdinaryFile::new(dir_entry.unwrap(), &config))
+            .collect();
+        let library_dir = PathBuf::from("/music/The Foos/The Foos are Back");
+        let sidecars = find_sidecars(&ordinary_files, &[dir.join("a.mp3")]);
+        let music_renames = vec![Rename::new(
+            dir.join("a.mp3"),
+            library_dir.join("01 The Foos - Foo.mp3"),
+        )];
+
+        // everything that isn't removed goes along with the music files
+        let renames = ordinary_renames(&ordinary_files, &sidecars, &library_dir, &config);
+        assert_eq!(
+            renames,
+            vec![Rename::new(
+                dir.join("booklet.pdf"),
+                library_dir.join("booklet.pdf")
+            )]
+        );
+        assert_eq!(
+            sidecar_renames(&sidecars, &music_renames, &config),
+            vec![Rename::new(
+                dir.join("a.lrc"),
+                library_dir.join("01 The Foos - Foo.lrc")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_limit_length() {
+        let temp_dir = TempDir::new("ordinary");
+        let dir = temp_dir.path();
+        fs::write(dir.join("a.lrc"), "foo").unwrap();
+        fs::write(dir.join("Front.jpeg"), "foo").unwrap();
+        let config = Config {
+            cover_name: Some(String::from("Album Cover")),
+            name_length: 21,
+            shorten_names: true,
+            ..Config::default()
+        };
+        let ordinary_files: Vec<OrdinaryFile> = fs::read_dir(dir)
+            .unwrap()
+            .map(|dir_entry| OrdinaryFile::new(dir_entry.unwrap(), &config))
+            .collect();
+        let sidecars = find_sidecars(&ordinary_files, &[dir.join("a.mp3")]);
+
+        let renames = ordinary_renames(&ordinary_files, &sidecars, dir, &config);
+        assert_eq!(
+            renames,
+            vec![Rename::new(
+                dir.join("Front.jpeg"),
+                dir.join("Album Cover.jpg")
+            )]
+        );
+
+        // sidecars aren't shortened on their own, but get their music file's shortened name
+        let config = Config {
+            name_length: 12,
+            ..config
+        };
+        let renames = ordinary_renames(&ordinary_files, &sidecars, dir, &config);
+        assert_eq!(
+            renames,
+            vec![Rename::new(dir.join("Front.jpeg"), dir.join("Album.jpg"))]
+        );
+    }
+
+    #[test]
+    fn test_file_pattern() {
+        let pattern = FilePattern::parse("*.jpg").unwrap();
+        assert!(pattern.is_match("cover.jpg"));
+        assert!(pattern.is_match("Cover.JPG"));
+        assert!(!pattern.is_match("cover.jpg.txt"));
+
+        let pattern = FilePattern::parse("folder.?ng").unwrap();
+        assert!(pattern.is_match("folder.png"));
+        assert!(!pattern.is_match("my folder.png"));
+
+        let pattern = FilePattern::parse("Thumbs.db").unwrap();
+        assert!(pattern.is_match("thumbs.db"));
+        assert!(!pattern.is_match("Thumbs_db"));
+
+        let pattern = FilePattern::parse(r"re:^scan\d+\.(jpg|png)$").unwrap();
+        assert!(pattern.is_match("scan01.png"));
+        assert!(!pattern.is_match("Scan01.png"));
+
+        assert!(FilePattern::parse("re:(").is_err());
     }
 }
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::conflict::Rename;
+use crate::ordinary_file;
+use crate::ordinary_file::Sidecar;
+use crate::util;
+
+/// Returns the length the components may have at most so that their lengths add up to at
+/// most `max_len`, or `None` if they already fit. Only the longest components are cut, all
+/// to the same length, so the cuts are distributed fairly.
+pub fn fair_limit(lengths: &[usize], max_len: usize) -> Option<usize> {
+    if lengths.iter().sum::<usize>() <= max_len {
+        return None;
+    }
+
+    let mut limit = lengths.iter().max().copied().unwrap_or_default();
+    while limit > 0 && lengths.iter().map(|len| *len.min(&limit)).sum::<usize>() > max_len {
+        limit -= 1;
+    }
+    Some(limit)
+}
+
+/// Shortens the file names and the names of the directories mp3rename chooses, so that the
+/// full paths of the files relative to the path root fit in `--max-path-length`.
+/// `target_dir` is the directory the files are renamed or moved to. In library mode, its
+/// components below the library root are shortened, else the directory's new name if it is
+/// renamed. The new paths of the renames must be in `target_dir`. Music files leave room
+/// for the names of their sidecars, which are named after them later.
+pub fn fit_path_length(
+    target_dir: &mut PathBuf,
+    new_directory_name: &mut Option<String>,
+    renames: &mut [Rename],
+    sidecars: &[Sidecar],
+    config: &Config,
+) {
+    let unit = config.length_unit;
+    let root = config
+        .path_root
+        .as_ref()
+        .or(config.library_root.as_ref())
+        .unwrap_or(&config.start_dir);
+
+    // the directory the files end up in, and how many of its last components are named by us
+    let (final_dir, named_dirs) = match (&config.library_root, &new_directory_name) {
+        (Some(library_root), _) => (
+            target_dir.clone(),
+            target_dir
+                .strip_prefix(library_root)
+                .map(|dirs| dirs.components().count())
+                .unwrap_or_default(),
+        ),
+        (None, Some(name)) => (target_dir.with_file_name(name), 1),
+        (None, None) => (target_dir.clone(), 0),
+    };
+
+    let components: Vec<String> = final_dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let dir_names = components[components.len().saturating_sub(named_dirs)..].to_vec();
+    let file_names: Vec<String> = renames.iter().map(|r| file_name(&r.new_path)).collect();
+    let excesses: Vec<usize> = renames
+        .iter()
+        .map(|r| ordinary_file::sidecar_excess(sidecars, &r.old_path, config))
+        .collect();
+    let longest_file_name = file_names
+        .iter()
+        .zip(&excesses)
+        .map(|(name, excess)| unit.length(name) + excess)
+        .max()
+        .unwrap_or_default();
+
+    // the length of the fixed directories and the separators
+    let relative_dir = final_dir.strip_prefix(root).unwrap_or(&final_dir);
+    let named_len: usize = dir_names.iter().map(|name| unit.length(name)).sum();
+    let overhead =
+        unit.length(&relative_dir.join("x").to_string_lossy()) - unit.length("x") - named_len;
+    let max_len = config.max_path_length.saturating_sub(overhead);
+
+    let mut lengths: Vec<usize> = dir_names.iter().map(|name| unit.length(name)).collect();
+    lengths.push(longest_file_name);
+    let limit = match fair_limit(&lengths, max_len) {
+        None => return,
+        Some(limit) => limit,
+    };
+
+    let dir_names: Vec<String> = dir_names
+        .iter()
+        .map(|name| util::shorten_name(Path::new(name), name, limit, config))
+        .collect();
+    if !dir_names.is_empty() {
+        match new_directory_name {
+            Some(name) if config.library_root.is_none() => *name = dir_names[0].clone(),
+            _ => {
+                let mut dir = final_dir.clone();
+                for _ in 0..named_dirs {
+                    dir.pop();
+                }
+                dir.extend(&dir_names);
+                *target_dir = dir;
+            }
+        }
+    }
+
+    // the file names get what the directories left
+    let max_file_name_len =
+        max_len.saturating_sub(dir_names.iter().map(|name| unit.length(name)).sum());
+    for ((rename, name), excess) in renames.iter_mut().zip(file_names).zip(excesses) {
+        let max_len = max_file_name_len.saturating_sub(excess);
+        let name = if unit.length(&name) <= max_len {
+            name
+        } else if util::get_extension(&rename.old_path).0.is_empty() {
+            // covers and the other files keep their own extensions
+            util::shorten_ordinary_name(&name, max_len, config)
+        } else {
+            util::shorten_name(&rename.old_path, &name, max_len, config)
+        };
+        rename.new_path = target_dir.join(name);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fair_limit() {
+        assert_eq!(fair_limit(&[10, 20], 30), None);
+        // only the longest component is cut as long as it is the longest
+        assert_eq!(fair_limit(&[10, 20], 25), Some(15));
+        assert_eq!(fair_limit(&[10, 20, 30], 45), Some(17));
+        assert_eq!(fair_limit(&[10, 20], 0), Some(0));
+    }
+
+    #[test]
+    fn test_fit_path_length() {
+        let config = Config {
+            library_root: Some(PathBuf::from("/music")),
+            max_path_length: 40,
+            start_dir: PathBuf::from("/downloads"),
+            ..Config::default()
+        };
+        let mut target_dir = PathBuf::from("/music/The Foos/The Foos are Back Again");
+        let mut renames = vec![
+            Rename::new(
+                PathBuf::from("/downloads/a.mp3"),
+                target_dir.join("01 The Foos - Foo de Foo.mp3"),
+            ),
+            Rename::new(
+                PathBuf::from("/downloads/b.mp3"),
+                target_dir.join("02 Foo.mp3"),
+            ),
+        ];
+
+        fit_path_length(&mut target_dir, &mut None, &mut renames, &[], &config);
+        assert_eq!(target_dir, PathBuf::from("/music/The Foos/The Foos are"));
+        assert_eq!(
+            renames[0].new_path,
+            PathBuf::from("/music/The Foos/The Foos are/01 The Foos.mp3")
+        );
+        assert_eq!(
+            renames[1].new_path,
+            PathBuf::from("/music/The Foos/The Foos are/02 Foo.mp3")
+        );
+        for rename in renames {
+            let relative_path = rename.new_path.strip_prefix("/music").unwrap();
+            assert!(relative_path.to_string_lossy().chars().count() <= 40);
+        }
+    }
+
+    #[test]
+    fn test_fit_path_length_with_sidecar() {
+        let config = Config {
+            max_path_length: 30,
+            start_dir: PathBuf::from("/music"),
+            ..Config::default()
+        };
+        let mut target_dir = PathBuf::from("/music/album");
+        let mut renames = vec![Rename::new(
+            PathBuf::from("/music/album/a.mp3"),
+            target_dir.join("01 The Foos - Foo de Foo.mp3"),
+        )];
+        let sidecars = vec![Sidecar {
+            path: PathBuf::from("/music/album/a.en.lrc"),
+            music_path: PathBuf::from("/music/album/a.mp3"),
+            suffix: String::from(".en.lrc"),
+        }];
+
+        fit_path_length(&mut target_dir, &mut None, &mut renames, &sidecars, &config);
+        // the track leaves room for the longer name of its lyrics, which are cut at the same place
+        assert_eq!(
+            renames[0].new_path,
+            PathBuf::from("/music/album/01 The Foos - Foo.mp3")
+        );
+        let sidecar_renames = ordinary_file::sidecar_renames(&sidecars, &renames, &config);
+        assert_eq!(
+            sidecar_renames[0].new_path,
+            PathBuf::from("/music/album/01 The Foos - Foo.en.lrc")
+        );
+        for rename in renames.iter().chain(&sidecar_renames) {
+            let relative_path = rename.new_path.strip_prefix("/music").unwrap();
+            assert!(relative_path.to_string_lossy().chars().count() <= 30);
+        }
+    }
+}
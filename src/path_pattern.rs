@@ -0,0 +1,87 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::template::{Placeholder, Template};
+
+/// A template like `"{artist}/{album}/{track} - {title}"` used the other way round: it is
+/// matched against the end of a music file's path to read the tags from the path.
+#[derive(Debug)]
+pub struct PathPattern {
+    template: Template,
+    regex: Regex,
+    placeholders: Vec<Placeholder>,
+}
+
+impl PathPattern {
+    pub fn parse(source: &str) -> Result<PathPattern, String> {
+        let template = Template::parse(source)?;
+        let (regex, placeholders) = template.to_regex();
+        // the pattern has to match whole directory and file names
+        let regex = Regex::new(&format!("(?:^|/){}$", regex)).map_err(|err| err.to_string())?;
+
+        Ok(PathPattern {
+            template,
+            regex,
+            placeholders,
+        })
+    }
+
+    /// Matches the path without its extension. Returns the values of all placeholders,
+    /// or `None` if the path doesn't fit the pattern.
+    pub fn captures(&self, path: &Path) -> Option<Vec<(Placeholder, String)>> {
+        let path = path
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("/");
+
+        let captures = self.regex.captures(&path)?;
+        Some(
+            self.placeholders
+                .iter()
+                .zip(captures.iter().skip(1))
+                .filter_map(|(placeholder, capture)| {
+                    capture.map(|c| (*placeholder, c.as_str().trim().to_string()))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captures() {
+        let pattern = PathPattern::parse("{artist}/{album}/{track} - {title}").unwrap();
+        assert_eq!(
+            pattern.captures(Path::new("/music/The Foos/Back/03 - Foo de Foo.mp3")),
+            Some(vec![
+                (Placeholder::Artist, String::from("The Foos")),
+                (Placeholder::Album, String::from("Back")),
+                (Placeholder::Track, String::from("03")),
+                (Placeholder::Title, String::from("Foo de Foo")),
+            ])
+        );
+        assert_eq!(
+            pattern.captures(Path::new("/music/The Foos/Back/Foo de Foo.mp3")),
+            None
+        );
+        // placeholders don't span several directories
+        assert_eq!(
+            pattern.captures(Path::new("Back/03 - Foo de Foo.mp3")),
+            None
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::journal::Action;
+use crate::rename_plan::PlannedAction;
+
+/// Keeps track of the files and directories a run moves away, so the directories left empty
+/// can be found even in dry-run mode, where nothing is moved
+#[derive(Debug, Default)]
+pub struct Pruner {
+    /// The paths that have been renamed or removed
+    gone: HashSet<PathBuf>,
+    /// The paths files have been renamed to and the directories that have been created
+    added: HashSet<PathBuf>,
+}
+
+impl Pruner {
+    /// Notes an action that has been carried out, or would have been in a dry run
+    pub fn note(&mut self, planned_action: &PlannedAction) {
+        let old_path = &planned_action.old_path;
+        match planned_action.action {
+            Action::CreateDirectory => {
+                self.added.insert(old_path.clone());
+            }
+            // the file stays where it is
+            Action::WriteTags => {}
+            _ => {
+                self.added.remove(old_path);
+                self.gone.insert(old_path.clone());
+                if let Some(new_path) = &planned_action.new_path {
+                    self.gone.remove(new_path);
+                    self.added.insert(new_path.clone());
+                }
+            }
+        }
+    }
+
+    /// Returns the junk files to remove along with the directory if the directory is left
+    /// with nothing but junk, i.e. files matching a `--delete` pattern, or `None` if it isn't
+    pub fn junk_in_empty_dir(&self, dir: &Path, config: &Config) -> Option<Vec<PathBuf>> {
+        if self.gone.contains(dir) || self.added.iter().any(|path| path.starts_with(dir)) {
+            return None;
+        }
+
+        let mut junk = Vec::new();
+        for dir_entry in fs::read_dir(dir).ok()? {
+            let path = dir_entry.ok()?.path();
+            if self.gone.contains(&path) {
+                continue;
+            }
+            if !path.is_file() || !is_junk(&path, config) {
+                return None;
+            }
+            junk.push(path);
+        }
+        junk.sort();
+        Some(junk)
+    }
+}
+
+fn is_junk(path: &Path, config: &Config) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    config
+        .delete_patterns
+        .iter()
+        .any(|pattern| pattern.is_match(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordinary_file::FilePattern;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_junk_in_empty_dir() {
+        let temp_dir = TempDir::new("prune");
+        let dir = temp_dir.path();
+        let album_dir = dir.join("album");
+        fs::create_dir_all(&album_dir).unwrap();
+        fs::write(album_dir.join("01 Foo.mp3"), "foo").unwrap();
+        fs::write(album_dir.join("Thumbs.db"), "foo").unwrap();
+        let config = Config {
+            delete_patterns: vec![FilePattern::parse("Thumbs.db").unwrap()],
+            ..Config::default()
+        };
+
+        let mut pruner = Pruner::default();
+        assert_eq!(pruner.junk_in_empty_dir(&album_dir, &config), None);
+
+        // the music file is moved away, but nothing is moved in a dry run
+        pruner.note(&PlannedAction {
+            action: Action::Rename,
+            old_path: album_dir.join("01 Foo.mp3"),
+            new_path: Some(dir.join("library").join("01 Foo.mp3")),
+            tags: None,
+        });
+        assert_eq!(
+            pruner.junk_in_empty_dir(&album_dir, &config),
+            Some(vec![album_dir.join("Thumbs.db")])
+        );
+        // the parent still contains the library the file has been moved to
+        assert_eq!(pruner.junk_in_empty_dir(dir, &config), None);
+
+        // a file moved in keeps the directory
+        pruner.note(&PlannedAction {
+            action: Action::Rename,
+            old_path: dir.join("cover.jpg"),
+            new_path: Some(album_dir.join("cover.jpg")),
+            tags: None,
+        });
+        assert_eq!(pruner.junk_in_empty_dir(&album_dir, &config), None);
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+
+use crate::conflict::Rename;
+use crate::journal::Action;
+use crate::tag_reader::RawTags;
+use crate::template::Placeholder;
+
+/// A single change to the file system
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedAction {
+    pub action: Action,
+    pub old_path: PathBuf,
+    pub new_path: Option<PathBuf>,
+    /// The tags to write, only for writing tags
+    pub tags: Option<TagChanges>,
+}
+
+/// New values for some of the tags of a music file
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagChanges {
+    pub tags: RawTags,
+    /// The fields to write, all other tags are kept
+    pub fields: Vec<Placeholder>,
+}
+
+impl fmt::Display for TagChanges {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let changes: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| format!("{}=\"{}\"", field.name(), self.tags.value(*field)))
+            .collect();
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+/// All changes to be done for a directory, in the order they have to be done.
+/// Both a dry run and a real run go through the same plan.
+#[derive(Debug, Default)]
+pub struct RenamePlan {
+    actions: Vec<PlannedAction>,
+}
+
+impl RenamePlan {
+    pub fn new() -> RenamePlan {
+        RenamePlan::default()
+    }
+
+    pub fn actions(&self) -> &[PlannedAction] {
+        &self.actions
+    }
+
+    pub fn create_directory(&mut self, path: &Path) {
+        self.actions.push(PlannedAction {
+            action: Action::CreateDirectory,
+            old_path: path.to_path_buf(),
+            new_path: None,
+            tags: None,
+        });
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.actions.push(PlannedAction {
+            action: Action::Remove,
+            old_path: path.to_path_buf(),
+            new_path: None,
+            tags: None,
+        });
+    }
+
+    /// Removes a directory that is empty by then
+    pub fn remove_directory(&mut self, path: &Path) {
+        self.actions.push(PlannedAction {
+            action: Action::RemoveDirectory,
+            old_path: path.to_path_buf(),
+            new_path: None,
+            tags: None,
+        });
+    }
+
+    /// Moves a file to the trash directory instead of removing it
+    pub fn trash(&mut self, path: &Path, trash_path: &Path) {
+        self.actions.push(PlannedAction {
+            action: Action::Trash,
+            old_path: path.to_path_buf(),
+            new_path: Some(trash_path.to_path_buf()),
+            tags: None,
+        });
+    }
+
+    /// Writes some of the tags of a music file
+    pub fn write_tags(&mut self, path: &Path, tags: RawTags, fields: Vec<Placeholder>) {
+        self.actions.push(PlannedAction {
+            action: Action::WriteTags,
+            old_path: path.to_path_buf(),
+            new_path: None,
+            tags: Some(TagChanges { tags, fields }),
+        });
+    }
+
+    /// Adds renames that have been checked for conflicts. They are ordered so that no file is
+    /// renamed to a name another file still has. Files swapping their names are renamed
+    /// to a temporary name first.
+    pub fn add_renames(&mut self, renames: Vec<Rename>) {
+        let mut pending = renames;
+
+        while !pending.is_empty() {
+            // a file can be renamed once no other file needs to give up its new name
+            let ready = pending.iter().position(|rename| {
+                !pending
+                    .iter()
+                    .any(|other| other.old_path == rename.new_path && other != rename)
+            });
+
+            match ready {
+                Some(index) => {
+                    let rename = pending.remove(index);
+                    self.rename(rename.old_path, rename.new_path);
+                }
+                None => {
+                    // all pending files are part of cycles, so break one up
+                    let temporary_path = temporary_path(&pending[0].old_path, &pending);
+                    self.rename(pending[0].old_path.clone(), temporary_path.clone());
+                    pending[0].old_path = temporary_path;
+                }
+            }
+        }
+    }
+
+    fn rename(&mut self, old_path: PathBuf, new_path: PathBuf) {
+        self.actions.push(PlannedAction {
+            action: Action::Rename,
+            old_path,
+            new_path: Some(new_path),
+            tags: None,
+        });
+    }
+}
+
+/// Returns whether the path is a temporary name given to break a cycle of renames
+pub fn is_temporary_path(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    name.starts_with('.')
+        && name
+            .rsplit_once(".mp3rename-")
+            .is_some_and(|(_, n)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Returns an unused name next to the file
+fn temporary_path(path: &Path, renames: &[Rename]) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| path.with_file_name(format!(".{}.mp3rename-{}", name, n)))
+        .find(|candidate| {
+            !candidate.exists()
+                && !renames
+                    .iter()
+                    .any(|r| r.old_path == *candidate || r.new_path == *candidate)
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(old_name: &str, new_name: &str) -> Rename {
+        Rename::new(
+            PathBuf::from(format!("/nonexistent/{}", old_name)),
+            PathBuf::from(format!("/nonexistent/{}", new_name)),
+        )
+    }
+
+    fn steps(plan: &RenamePlan) -> Vec<(String, String)> {
+        plan.actions()
+            .iter()
+            .map(|a| {
+                (
+                    a.old_path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                    a.new_path
+                        .as_ref()
+                        .unwrap()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn step(old_name: &str, new_name: &str) -> (String, String) {
+        (old_name.to_string(), new_name.to_string())
+    }
+
+    #[test]
+    fn test_independent_renames_keep_their_order() {
+        let mut plan = RenamePlan::new();
+        plan.add_renames(vec![rename("b", "1"), rename("a", "2")]);
+        assert_eq!(steps(&plan), vec![step("b", "1"), step("a", "2")]);
+    }
+
+    #[test]
+    fn test_chain() {
+        // "a" can only take its new name after "b" has given it up, and so on
+        let mut plan = RenamePlan::new();
+        plan.add_renames(vec![rename("a", "b"), rename("b", "c"), rename("c", "d")]);
+        assert_eq!(
+            steps(&plan),
+            vec![step("c", "d"), step("b", "c"), step("a", "b")]
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut plan = RenamePlan::new();
+        plan.add_renames(vec![rename("a", "b"), rename("b", "a")]);
+        assert_eq!(
+            steps(&plan),
+            vec![
+                step("a", ".a.mp3rename-1"),
+                step("b", "a"),
+                step(".a.mp3rename-1", "b")
+            ]
+        );
+        assert!(is_temporary_path(Path::new("/music/.a.mp3rename-1")));
+        assert!(!is_temporary_path(Path::new("/music/a.mp3rename-1")));
+        assert!(!is_temporary_path(Path::new("/music/.a.mp3rename-x")));
+    }
+
+    #[test]
+    fn test_cycle_and_chain() {
+        // the chain is renamed first, then the cycle is broken up at its first file
+        let mut plan = RenamePlan::new();
+        plan.add_renames(vec![
+            rename("a", "b"),
+            rename("b", "c"),
+            rename("c", "a"),
+            rename("x", "y"),
+            rename("y", "z"),
+        ]);
+        assert_eq!(
+            steps(&plan),
+            vec![
+                step("y", "z"),
+                step("x", "y"),
+                step("a", ".a.mp3rename-1"),
+                step("c", "a"),
+                step("b", "c"),
+                step(".a.mp3rename-1", "b")
+            ]
+        );
+    }
+}
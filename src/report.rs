@@ -0,0 +1,459 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::journal::Action;
+use crate::rename_plan::PlannedAction;
+
+/// How the planned actions are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Progress messages for humans
+    #[default]
+    Text,
+    /// A JSON array of records
+    Json,
+    /// Comma-separated records with a header line
+    Csv,
+    /// Tab-separated records with a header line
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            _ => None,
+        }
+    }
+}
+
+/// What is done to a file or directory
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordAction {
+    CreateDirectory,
+    /// Rename within the same directory
+    Rename,
+    /// Rename to another directory
+    Move,
+    Remove,
+    /// Remove an empty directory
+    RemoveDirectory,
+    /// Move to the trash directory
+    Trash,
+    /// Left alone, see the warnings
+    Skip,
+    /// Write the normalized tags to a music file
+    WriteTags,
+}
+
+impl RecordAction {
+    fn name(&self) -> &'static str {
+        match self {
+            RecordAction::CreateDirectory => "create-directory",
+            RecordAction::Rename => "rename",
+            RecordAction::Move => "move",
+            RecordAction::Remove => "remove",
+            RecordAction::RemoveDirectory => "remove-directory",
+            RecordAction::Trash => "trash",
+            RecordAction::Skip => "skip",
+            RecordAction::WriteTags => "write-tags",
+        }
+    }
+}
+
+/// Identifies the contents of a file to find out whether it has changed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+    pub sha256: String,
+}
+
+impl Fingerprint {
+    pub fn new(path: &Path) -> io::Result<Fingerprint> {
+        let metadata = path.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+
+        Ok(Fingerprint {
+            size: metadata.len(),
+            modified,
+            sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+/// One planned action in machine-readable form. The names are relative to the directory
+/// if they are inside it, and absolute otherwise.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub directory: PathBuf,
+    pub action: RecordAction,
+    pub old_name: PathBuf,
+    pub new_name: Option<PathBuf>,
+    /// The tags the new name has been built from
+    pub tags: BTreeMap<String, String>,
+    pub warnings: Vec<String>,
+    /// The file as it was when the plan was made, only written to JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl Record {
+    pub fn old_path(&self) -> PathBuf {
+        self.directory.join(&self.old_name)
+    }
+
+    /// Returns the action to carry out, or None if the file is left alone. The values of
+    /// the tags to write aren't part of the record, so tags are never written.
+    pub fn planned_action(&self) -> Option<PlannedAction> {
+        let action = match self.action {
+            RecordAction::CreateDirectory => Action::CreateDirectory,
+            RecordAction::Rename | RecordAction::Move => Action::Rename,
+            RecordAction::Remove => Action::Remove,
+            RecordAction::RemoveDirectory => Action::RemoveDirectory,
+            RecordAction::Trash => Action::Trash,
+            RecordAction::Skip | RecordAction::WriteTags => return None,
+        };
+
+        Some(PlannedAction {
+            action,
+            old_path: self.old_path(),
+            new_path: self.new_name.as_ref().map(|name| self.directory.join(name)),
+            tags: None,
+        })
+    }
+}
+
+/// Reads the records written with `--output-format json`
+pub fn read_plan(path: &Path) -> io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Collects a record for each planned action and the warnings that came up while planning
+#[derive(Default)]
+pub struct Report {
+    /// Whether to fingerprint the files, which takes time
+    fingerprints: bool,
+    directory: PathBuf,
+    tags: HashMap<PathBuf, BTreeMap<String, String>>,
+    directory_warnings: Vec<String>,
+    file_warnings: HashMap<PathBuf, Vec<String>>,
+    /// The temporary names of files and their original paths
+    temporary_paths: HashMap<PathBuf, PathBuf>,
+    records: Vec<Record>,
+}
+
+impl Report {
+    pub fn new(format: OutputFormat) -> Report {
+        Report {
+            // only JSON output can be applied later
+            fingerprints: format == OutputFormat::Json,
+            ..Report::default()
+        }
+    }
+
+    /// Starts collecting the records of another directory
+    pub fn enter_directory(&mut self, directory: &Path) {
+        self.directory = directory.to_path_buf();
+        self.tags.clear();
+        self.directory_warnings.clear();
+        self.file_warnings.clear();
+        self.temporary_paths.clear();
+    }
+
+    pub fn add_tags(&mut self, path: &Path, tags: BTreeMap<String, String>) {
+        self.tags.insert(path.to_path_buf(), tags);
+    }
+
+    /// Prints a warning concerning the whole directory and adds it to all of its records
+    pub fn warn(&mut self, warning: String) {
+        eprintln!("{}", warning);
+        self.directory_warnings.push(warning);
+    }
+
+    /// Prints a warning concerning a single file and adds it to the file's record
+    pub fn warn_about(&mut self, path: &Path, warning: String) {
+        eprintln!("{}", warning);
+        self.file_warnings
+            .entry(path.to_path_buf())
+            .or_default()
+            .push(warning);
+    }
+
+    /// Records that a file or directory is left alone
+    pub fn skip(&mut self, path: &Path) {
+        self.push(RecordAction::Skip, path, None);
+    }
+
+    pub fn record(&mut self, planned_action: &PlannedAction) {
+        let old_path = &planned_action.old_path;
+        let action = match (planned_action.action, &planned_action.new_path) {
+            (Action::CreateDirectory, _) => RecordAction::CreateDirectory,
+            (Action::RemoveDirectory, _) => RecordAction::RemoveDirectory,
+            (Action::Remove, _) | (Action::Rename, None) | (Action::Trash, None) => {
+                RecordAction::Remove
+            }
+            (Action::Trash, Some(_)) => RecordAction::Trash,
+            (Action::WriteTags, _) => RecordAction::WriteTags,
+            (Action::Rename, Some(new_path)) => {
+                // remember where files that are renamed in several steps came from
+                let original_path = self
+                    .temporary_paths
+                    .get(old_path)
+                    .unwrap_or(old_path)
+                    .clone();
+                self.temporary_paths.insert(new_path.clone(), original_path);
+
+                if old_path.parent() == new_path.parent() {
+                    RecordAction::Rename
+                } else {
+                    RecordAction::Move
+                }
+            }
+        };
+        self.push(action, old_path, planned_action.new_path.as_deref());
+    }
+
+    fn push(&mut self, action: RecordAction, old_path: &Path, new_path: Option<&Path>) {
+        let original_path = self.temporary_paths.get(old_path).map_or(old_path, |p| p);
+        let mut warnings = self.directory_warnings.clone();
+        if let Some(file_warnings) = self.file_warnings.get(original_path) {
+            warnings.extend(file_warnings.iter().cloned());
+        }
+
+        let fingerprint = match action {
+            RecordAction::Rename
+            | RecordAction::Move
+            | RecordAction::Remove
+            | RecordAction::Trash
+                if self.fingerprints && old_path.is_file() =>
+            {
+                Fingerprint::new(old_path).ok()
+            }
+            _ => None,
+        };
+
+        self.records.push(Record {
+            directory: self.directory.clone(),
+            action,
+            old_name: self.relative_path(old_path),
+            new_name: new_path.map(|p| self.relative_path(p)),
+            tags: self.tags.get(original_path).cloned().unwrap_or_default(),
+            warnings,
+            fingerprint,
+        });
+    }
+
+    fn relative_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.directory) {
+            Ok(rest) if !rest.as_os_str().is_empty() => rest.to_path_buf(),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Writes the records in a machine-readable format. Text output has already been
+    /// printed while executing the plan, so nothing is written for it.
+    pub fn write<W: Write>(&self, out: &mut W, format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, &self.records)?;
+                writeln!(out)
+            }
+            OutputFormat::Csv => self.write_separated(out, ",", csv_field),
+            OutputFormat::Tsv => self.write_separated(out, "\t", tsv_field),
+        }
+    }
+
+    fn write_separated<W: Write>(
+        &self,
+        out: &mut W,
+        separator: &str,
+        field: fn(&str) -> String,
+    ) -> io::Result<()> {
+        writeln!(
+            out,
+            "{}",
+            [
+                "directory",
+                "action",
+                "old_name",
+                "new_name",
+                "tags",
+                "warnings"
+            ]
+            .join(separator)
+        )?;
+        for record in &self.records {
+            let tags: Vec<String> = record
+                .tags
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            let fields = [
+                record.directory.to_string_lossy().to_string(),
+                record.action.name().to_string(),
+                record.old_name.to_string_lossy().to_string(),
+                record
+                    .new_name
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                tags.join("; "),
+                record.warnings.join("; "),
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| field(f)).collect();
+            writeln!(out, "{}", fields.join(separator))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes a field if it contains a comma, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// TSV has no quoting, so tabs and line breaks are replaced by spaces
+fn tsv_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rename_plan::RenamePlan;
+    use crate::tag_reader::RawTags;
+    use crate::template::Placeholder;
+    use crate::test_util::TempDir;
+
+    fn rename(old_path: &str, new_path: &str) -> PlannedAction {
+        PlannedAction {
+            action: Action::Rename,
+            old_path: PathBuf::from(old_path),
+            new_path: Some(PathBuf::from(new_path)),
+            tags: None,
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let mut report = Report::new(OutputFormat::Csv);
+        report.enter_directory(Path::new("/music/foo"));
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("title"), String::from("A, B"));
+        report.add_tags(Path::new("/music/foo/a.mp3"), tags.clone());
+        report.warn_about(Path::new("/music/foo/a.mp3"), String::from("Conflict"));
+
+        // a file renamed via a temporary name keeps its tags and warnings
+        report.record(&rename("/music/foo/a.mp3", "/music/foo/.a.mp3.mp3rename-1"));
+        report.record(&rename(
+            "/music/foo/.a.mp3.mp3rename-1",
+            "/music/foo/1 A, B.mp3",
+        ));
+        report.record(&rename("/music/foo", "/music/Foo"));
+
+        let records = &report.records;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].action, RecordAction::Rename);
+        assert_eq!(records[1].old_name, PathBuf::from(".a.mp3.mp3rename-1"));
+        assert_eq!(records[1].new_name, Some(PathBuf::from("1 A, B.mp3")));
+        assert_eq!(records[1].tags, tags);
+        assert_eq!(records[1].warnings, vec![String::from("Conflict")]);
+        assert_eq!(records[2].old_name, PathBuf::from("/music/foo"));
+        assert!(records[2].tags.is_empty());
+
+        let mut csv = Vec::new();
+        report.write(&mut csv, OutputFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "directory,action,old_name,new_name,tags,warnings");
+        assert_eq!(
+            lines[2],
+            "/music/foo,rename,.a.mp3.mp3rename-1,\"1 A, B.mp3\",\"title=A, B\",Conflict"
+        );
+
+        let mut json = Vec::new();
+        report.write(&mut json, OutputFormat::Json).unwrap();
+        let parsed: Vec<Record> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(&parsed, records);
+    }
+
+    #[test]
+    fn test_write_tags() {
+        let mut report = Report::new(OutputFormat::Json);
+        report.enter_directory(Path::new("/music/foo"));
+        let mut plan = RenamePlan::new();
+        plan.write_tags(
+            Path::new("/music/foo/a.mp3"),
+            RawTags::default(),
+            vec![Placeholder::Title],
+        );
+        report.record(&plan.actions()[0]);
+
+        let record = &report.records[0];
+        assert_eq!(record.action, RecordAction::WriteTags);
+        assert_eq!(record.old_name, PathBuf::from("a.mp3"));
+        assert_eq!(record.new_name, None);
+        // the values of the tags aren't kept, so a plan doesn't write them
+        assert_eq!(record.planned_action(), None);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let temp_dir = TempDir::new("report");
+        let dir = temp_dir.path();
+        let path = dir.join("a.mp3");
+        std::fs::write(&path, "foo").unwrap();
+
+        let mut report = Report::new(OutputFormat::Json);
+        report.enter_directory(dir);
+        report.record(&rename(
+            &path.to_string_lossy(),
+            &dir.join("b.mp3").to_string_lossy(),
+        ));
+
+        let record = &report.records[0];
+        let fingerprint = record.fingerprint.as_ref().unwrap();
+        assert_eq!(fingerprint.size, 3);
+        assert_eq!(
+            fingerprint.sha256,
+            "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+        );
+        assert_eq!(Fingerprint::new(&path).unwrap(), *fingerprint);
+        assert_eq!(
+            record.planned_action(),
+            Some(rename(
+                &path.to_string_lossy(),
+                &dir.join("b.mp3").to_string_lossy(),
+            ))
+        );
+
+        std::fs::write(&path, "bar").unwrap();
+        assert_ne!(Fingerprint::new(&path).unwrap(), *fingerprint);
+    }
+}
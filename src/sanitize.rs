@@ -0,0 +1,144 @@
+/// The rules for names on a target file system
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SanitizeProfile {
+    /// Linux and macOS: only "/" is forbidden
+    Posix,
+    /// Windows (NTFS), which also fits the other systems
+    #[default]
+    Windows,
+    /// FAT32 and exFAT USB sticks and SD cards
+    Fat32,
+    /// Network shares accessed by Windows clients
+    Smb,
+    /// Only letters, digits, and a few punctuation marks of ASCII, for picky devices
+    StrictAscii,
+}
+
+/// Device names Windows doesn't allow as names, even with an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The punctuation marks `strict-ascii` keeps
+const STRICT_ASCII_PUNCTUATION: &str = " !&'()+,-.;=[]_";
+
+impl SanitizeProfile {
+    pub fn from_name(name: &str) -> Option<SanitizeProfile> {
+        match name {
+            "posix" => Some(SanitizeProfile::Posix),
+            "windows" => Some(SanitizeProfile::Windows),
+            "fat32" => Some(SanitizeProfile::Fat32),
+            "smb" => Some(SanitizeProfile::Smb),
+            "strict-ascii" => Some(SanitizeProfile::StrictAscii),
+            _ => None,
+        }
+    }
+
+    /// Returns what a character not allowed in names is replaced with, or `None` if the
+    /// character is allowed
+    pub fn replacement(&self, c: char) -> Option<&'static str> {
+        if *self == SanitizeProfile::Posix {
+            return match c {
+                '/' => Some(" & "),
+                '\0' => Some(""),
+                _ => None,
+            };
+        }
+
+        match c {
+            // "$" breaks shell scripts and hides shares
+            '$' => Some("_"),
+            '\\' => Some(""),
+            '/' => Some(" & "),
+            ':' => Some(" -"),
+            '*' => Some("_"),
+            '?' => Some(""),
+            '"' => Some(""),
+            '<' => Some(""),
+            '>' => Some(""),
+            '|' => Some(", "),
+            // tabs and the like are turned into blanks, which are collapsed later
+            c if c.is_whitespace() => None,
+            c if (c as u32) < 0x20 => Some(""),
+            // FAT doesn't allow DEL either
+            '\u{7f}' if *self == SanitizeProfile::Fat32 => Some(""),
+            c if *self == SanitizeProfile::StrictAscii
+                && !c.is_ascii_alphanumeric()
+                && !STRICT_ASCII_PUNCTUATION.contains(c) =>
+            {
+                Some("_")
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks if Windows device names like "CON" or "COM1" must be avoided
+    pub fn has_reserved_names(&self) -> bool {
+        *self != SanitizeProfile::Posix
+    }
+
+    /// Checks if names must not end with a dot or a blank
+    pub fn strips_trailing_dots(&self) -> bool {
+        *self != SanitizeProfile::Posix
+    }
+
+    /// Checks if a name is a Windows device name, ignoring case and any extension
+    pub fn is_reserved_name(&self, name: &str) -> bool {
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        self.has_reserved_names()
+            && RESERVED_NAMES
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    }
+}
+
+/// Parses a replacement like "???=Fragezeichen"
+pub fn parse_replacement(replacement: &str) -> Result<(String, String), String> {
+    match replacement.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!(
+            "Cannot parse replacement \"{}\", expected FROM=TO",
+            replacement
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replacement() {
+        assert_eq!(SanitizeProfile::Posix.replacement(':'), None);
+        assert_eq!(SanitizeProfile::Windows.replacement(':'), Some(" -"));
+        assert_eq!(SanitizeProfile::Windows.replacement('é'), None);
+        assert_eq!(SanitizeProfile::Fat32.replacement('\u{7f}'), Some(""));
+        assert_eq!(SanitizeProfile::StrictAscii.replacement('é'), Some("_"));
+        assert_eq!(SanitizeProfile::StrictAscii.replacement('-'), None);
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        assert!(SanitizeProfile::Windows.is_reserved_name("con"));
+        assert!(SanitizeProfile::Smb.is_reserved_name("COM1.mp3"));
+        assert!(SanitizeProfile::Windows.is_reserved_name("CON.Live"));
+        assert!(!SanitizeProfile::Windows.is_reserved_name("CON_.Live"));
+        assert!(!SanitizeProfile::Windows.is_reserved_name("Conan"));
+        assert!(!SanitizeProfile::Posix.is_reserved_name("CON"));
+    }
+
+    #[test]
+    fn test_parse_replacement() {
+        assert_eq!(
+            parse_replacement("???=Fragezeichen"),
+            Ok((String::from("???"), String::from("Fragezeichen")))
+        );
+        assert_eq!(
+            parse_replacement("&=and"),
+            Ok((String::from("&"), String::from("and")))
+        );
+        assert!(parse_replacement("foo").is_err());
+        assert!(parse_replacement("=foo").is_err());
+    }
+}
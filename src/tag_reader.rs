@@ -0,0 +1,600 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use id3::TagLike;
+
+use crate::template::Placeholder;
+
+/// The tags as read from a music file, before checking they are complete
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RawTags {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub artist: Option<String>,
+    pub comment: Option<String>,
+    /// Whether the album is a compilation of several artists' tracks
+    pub compilation: bool,
+    pub composer: Option<String>,
+    pub disc_number: Option<u16>,
+    pub genre: Option<String>,
+    pub title: Option<String>,
+    pub total_discs: Option<u16>,
+    pub total_tracks: Option<u16>,
+    pub track_number: Option<u16>,
+    pub year: Option<i32>,
+}
+
+impl RawTags {
+    /// Builds the tags from key-value pairs like Vorbis comments or APE items,
+    /// whose keys are case-insensitive
+    fn from_fields<I>(fields: I) -> RawTags
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut tags = RawTags::default();
+        for (key, value) in fields {
+            match key.to_uppercase().as_str() {
+                "ALBUM" => tags.album = Some(value),
+                "ALBUMARTIST" | "ALBUM ARTIST" => tags.album_artist = Some(value),
+                "ARTIST" => tags.artist = Some(value),
+                "COMMENT" | "DESCRIPTION" => tags.comment = Some(value),
+                "COMPILATION" => tags.compilation = parse_flag(&value),
+                "COMPOSER" => tags.composer = Some(value),
+                "DATE" | "YEAR" => tags.year = parse_year(&value),
+                "DISCNUMBER" | "DISC" => {
+                    let (number, total) = parse_number_pair(&value);
+                    tags.disc_number = number;
+                    tags.total_discs = tags.total_discs.or(total);
+                }
+                "DISCTOTAL" | "TOTALDISCS" => tags.total_discs = parse_number(&value),
+                "GENRE" => tags.genre = Some(value),
+                "TITLE" => tags.title = Some(value),
+                "TRACKNUMBER" | "TRACK" => {
+                    let (number, total) = parse_number_pair(&value);
+                    tags.track_number = number;
+                    tags.total_tracks = tags.total_tracks.or(total);
+                }
+                "TRACKTOTAL" | "TOTALTRACKS" => tags.total_tracks = parse_number(&value),
+                _ => {}
+            }
+        }
+        tags
+    }
+
+    /// Fills the tags that are missing with the ones read by another reader
+    fn complete_with(self, other: RawTags) -> RawTags {
+        RawTags {
+            album: self.album.or(other.album),
+            album_artist: self.album_artist.or(other.album_artist),
+            artist: self.artist.or(other.artist),
+            comment: self.comment.or(other.comment),
+            compilation: self.compilation || other.compilation,
+            composer: self.composer.or(other.composer),
+            disc_number: self.disc_number.or(other.disc_number),
+            genre: self.genre.or(other.genre),
+            title: self.title.or(other.title),
+            total_discs: self.total_discs.or(other.total_discs),
+            total_tracks: self.total_tracks.or(other.total_tracks),
+            track_number: self.track_number.or(other.track_number),
+            year: self.year.or(other.year),
+        }
+    }
+
+    /// Returns the value of a tag as text, or the empty string if it is missing
+    pub fn value(&self, placeholder: Placeholder) -> String {
+        let number = |n: Option<u16>| n.map(|n| n.to_string()).unwrap_or_default();
+        match placeholder {
+            Placeholder::Album => self.album.clone().unwrap_or_default(),
+            Placeholder::AlbumArtist => self.album_artist.clone().unwrap_or_default(),
+            Placeholder::Artist => self.artist.clone().unwrap_or_default(),
+            Placeholder::Composer => self.composer.clone().unwrap_or_default(),
+            Placeholder::Disc => number(self.disc_number),
+            Placeholder::DiscTotal => number(self.total_discs),
+            Placeholder::Genre => self.genre.clone().unwrap_or_default(),
+            Placeholder::Title => self.title.clone().unwrap_or_default(),
+            Placeholder::Track => number(self.track_number),
+            Placeholder::TrackTotal => number(self.total_tracks),
+            Placeholder::Year => self.year.map(|n| n.to_string()).unwrap_or_default(),
+            Placeholder::Format => String::new(),
+        }
+    }
+
+    /// Sets the tags to the values found in a path, see `PathPattern::captures`. Unless
+    /// `overwrite` is set, only missing tags are set. Returns the placeholders whose
+    /// values were used.
+    pub fn set_from_path(
+        &mut self,
+        captures: Vec<(Placeholder, String)>,
+        overwrite: bool,
+    ) -> Vec<Placeholder> {
+        fn set<T: PartialEq>(field: &mut Option<T>, value: Option<T>, overwrite: bool) -> bool {
+            if value.is_some() && (field.is_none() || overwrite) && *field != value {
+                *field = value;
+                true
+            } else {
+                false
+            }
+        }
+
+        let mut used = Vec::new();
+        for (placeholder, value) in captures {
+            let text = Some(value.clone()).filter(|v| !v.is_empty());
+            let is_used = match placeholder {
+                Placeholder::Album => set(&mut self.album, text, overwrite),
+                Placeholder::AlbumArtist => set(&mut self.album_artist, text, overwrite),
+                Placeholder::Artist => set(&mut self.artist, text, overwrite),
+                Placeholder::Composer => set(&mut self.composer, text, overwrite),
+                Placeholder::Disc => set(&mut self.disc_number, parse_number(&value), overwrite),
+                Placeholder::DiscTotal => {
+                    set(&mut self.total_discs, parse_number(&value), overwrite)
+                }
+                Placeholder::Genre => set(&mut self.genre, text, overwrite),
+                Placeholder::Title => set(&mut self.title, text, overwrite),
+                Placeholder::Track => set(&mut self.track_number, parse_number(&value), overwrite),
+                Placeholder::TrackTotal => {
+                    set(&mut self.total_tracks, parse_number(&value), overwrite)
+                }
+                Placeholder::Year => set(&mut self.year, parse_year(&value), overwrite),
+                // the format is always taken from the extension
+                Placeholder::Format => false,
+            };
+            if is_used && !used.contains(&placeholder) {
+                used.push(placeholder);
+            }
+        }
+        used
+    }
+}
+
+/// Reads the tags of a music file, choosing the tag format by the file's extension
+pub fn read_tags(path: &Path) -> Result<RawTags, String> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "ogg" | "oga" | "opus" => read_vorbis_comments(path).map_err(|e| e.to_string()),
+        // the ID3 tag is in a chunk of the RIFF or AIFF container
+        "wav" | "aif" | "aiff" => read_id3(path).map_err(|e| e.to_string()),
+        "wv" => read_ape_tag(path).map_err(|e| e.to_string()),
+        _ => {
+            let tags = read_audiotags(path)?;
+            // audiotags knows neither genre, composer, comment, nor the compilation flag,
+            // so these are read from the format's own tag
+            let format_tags = match extension.as_str() {
+                "flac" => read_flac(path).map_err(|e| e.to_string())?,
+                "m4a" | "m4b" | "m4p" | "m4v" => read_mp4(path).map_err(|e| e.to_string())?,
+                _ => read_id3(path).map_err(|e| e.to_string())?,
+            };
+            Ok(tags.complete_with(format_tags))
+        }
+    }
+}
+
+fn read_audiotags(path: &Path) -> Result<RawTags, String> {
+    let tag = audiotags::Tag::new()
+        .read_from_path(path)
+        .map_err(|e| e.to_string())?;
+
+    Ok(RawTags {
+        album: tag.album_title().map(String::from),
+        album_artist: tag.album_artist().map(String::from),
+        artist: tag.artist().map(String::from),
+        disc_number: tag.disc_number(),
+        title: tag.title().map(String::from),
+        total_discs: tag.total_discs(),
+        total_tracks: tag.total_tracks(),
+        track_number: tag.track_number(),
+        year: tag.year(),
+        ..RawTags::default()
+    })
+}
+
+fn read_id3(path: &Path) -> id3::Result<RawTags> {
+    let tag = id3::Tag::read_from_path(path)?;
+    let comment = tag.comments().next().map(|comment| comment.text.clone());
+
+    Ok(RawTags {
+        album: tag.album().map(String::from),
+        album_artist: tag.album_artist().map(String::from),
+        artist: tag.artist().map(String::from),
+        comment,
+        // iTunes' unofficial compilation frame
+        compilation: tag
+            .get("TCMP")
+            .and_then(|frame| frame.content().text())
+            .is_some_and(parse_flag),
+        composer: tag
+            .get("TCOM")
+            .and_then(|frame| frame.content().text())
+            .map(String::from),
+        disc_number: tag.disc().and_then(|n| u16::try_from(n).ok()),
+        // genres may be given as ID3v1 genre numbers like "(17)"
+        genre: tag.genre_parsed().map(|genre| genre.to_string()),
+        title: tag.title().map(String::from),
+        total_discs: tag.total_discs().and_then(|n| u16::try_from(n).ok()),
+        total_tracks: tag.total_tracks().and_then(|n| u16::try_from(n).ok()),
+        track_number: tag.track().and_then(|n| u16::try_from(n).ok()),
+        // ID3v2.4 replaced the year with the recording time
+        year: tag
+            .year()
+            .or_else(|| tag.date_recorded().map(|date| date.year)),
+    })
+}
+
+fn read_flac(path: &Path) -> metaflac::Result<RawTags> {
+    let tag = metaflac::Tag::read_from_path(path)?;
+    let fields = tag
+        .vorbis_comments()
+        .map(|comments| {
+            comments
+                .comments
+                .iter()
+                .filter_map(|(key, values)| Some((key.clone(), values.first()?.clone())))
+                .collect::<Vec<(String, String)>>()
+        })
+        .unwrap_or_default();
+    Ok(RawTags::from_fields(fields))
+}
+
+fn read_mp4(path: &Path) -> mp4ameta::Result<RawTags> {
+    let tag = mp4ameta::Tag::read_from_path(path)?;
+
+    Ok(RawTags {
+        album: tag.album().map(String::from),
+        album_artist: tag.album_artist().map(String::from),
+        artist: tag.artist().map(String::from),
+        comment: tag.comment().map(String::from),
+        compilation: tag.compilation(),
+        composer: tag.composer().map(String::from),
+        disc_number: tag.disc_number(),
+        genre: tag.genre().map(String::from),
+        title: tag.title().map(String::from),
+        total_discs: tag.total_discs(),
+        total_tracks: tag.total_tracks(),
+        track_number: tag.track_number(),
+        year: tag.year().and_then(parse_year),
+    })
+}
+
+/// Reads the comment header of an Ogg Vorbis or Opus stream, which is its second packet
+fn read_vorbis_comments(path: &Path) -> std::io::Result<RawTags> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet = Vec::new();
+    let mut serial = None;
+
+    while packets.len() < 2 {
+        // page header: capture pattern, version, header type, granule position,
+        // serial number, sequence number, checksum, and number of segments
+        let mut header = [0u8; 27];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != b"OggS" {
+            return Err(invalid_data("Not an Ogg file"));
+        }
+        let page_serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+        let mut segment_table = vec![0u8; header[26] as usize];
+        reader.read_exact(&mut segment_table)?;
+
+        // skip pages of other logical streams
+        if *serial.get_or_insert(page_serial) != page_serial {
+            let page_size: u64 = segment_table.iter().map(|&s| s as u64).sum();
+            reader.seek(SeekFrom::Current(page_size as i64))?;
+            continue;
+        }
+
+        for segment_size in segment_table {
+            let mut segment = vec![0u8; segment_size as usize];
+            reader.read_exact(&mut segment)?;
+            packet.extend_from_slice(&segment);
+            // a segment shorter than 255 bytes ends a packet
+            if segment_size < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+    }
+
+    let comments = &packets[1];
+    let comments = if let Some(rest) = comments.strip_prefix(b"\x03vorbis") {
+        rest
+    } else if let Some(rest) = comments.strip_prefix(b"OpusTags") {
+        rest
+    } else {
+        return Err(invalid_data("No Vorbis comment header"));
+    };
+
+    Ok(RawTags::from_fields(parse_vorbis_comments(comments)?))
+}
+
+fn parse_vorbis_comments(data: &[u8]) -> std::io::Result<Vec<(String, String)>> {
+    let mut data = data;
+    let vendor_length = read_u32_le(&mut data)? as usize;
+    take(&mut data, vendor_length)?;
+
+    let count = read_u32_le(&mut data)?;
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        let length = read_u32_le(&mut data)? as usize;
+        let comment = String::from_utf8_lossy(take(&mut data, length)?);
+        if let Some((key, value)) = comment.split_once('=') {
+            fields.push((key.to_string(), value.to_string()));
+        }
+    }
+    Ok(fields)
+}
+
+/// Reads the APEv2 tag WavPack files carry at their end, possibly followed by an ID3v1 tag
+fn read_ape_tag(path: &Path) -> std::io::Result<RawTags> {
+    const FOOTER_SIZE: u64 = 32;
+    const ID3V1_SIZE: u64 = 128;
+
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut footer_end = file_size;
+    if file_size >= ID3V1_SIZE {
+        let mut id3v1 = [0u8; 3];
+        file.seek(SeekFrom::Start(file_size - ID3V1_SIZE))?;
+        file.read_exact(&mut id3v1)?;
+        if &id3v1 == b"TAG" {
+            footer_end -= ID3V1_SIZE;
+        }
+    }
+    if footer_end < FOOTER_SIZE {
+        return Err(invalid_data("No APE tag"));
+    }
+
+    // footer: preamble, version, tag size (items and footer), item count, flags, reserved
+    let mut footer = [0u8; FOOTER_SIZE as usize];
+    file.seek(SeekFrom::Start(footer_end - FOOTER_SIZE))?;
+    file.read_exact(&mut footer)?;
+    if &footer[0..8] != b"APETAGEX" {
+        return Err(invalid_data("No APE tag"));
+    }
+    let tag_size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]) as u64;
+    let item_count = u32::from_le_bytes([footer[16], footer[17], footer[18], footer[19]]);
+    if tag_size < FOOTER_SIZE || tag_size > footer_end {
+        return Err(invalid_data("Invalid APE tag size"));
+    }
+
+    let mut items = vec![0u8; (tag_size - FOOTER_SIZE) as usize];
+    file.seek(SeekFrom::Start(footer_end - tag_size))?;
+    file.read_exact(&mut items)?;
+
+    let mut data = items.as_slice();
+    let mut fields = Vec::new();
+    for _ in 0..item_count {
+        let value_size = read_u32_le(&mut data)? as usize;
+        let flags = read_u32_le(&mut data)?;
+        let key_end = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("Invalid APE item key"))?;
+        let key = String::from_utf8_lossy(&data[..key_end]).to_string();
+        data = &data[key_end + 1..];
+        let value = take(&mut data, value_size)?;
+
+        // only text items are of interest, not binary ones like cover art
+        if flags & 0b110 == 0 {
+            fields.push((key, String::from_utf8_lossy(value).to_string()));
+        }
+    }
+
+    Ok(RawTags::from_fields(fields))
+}
+
+/// Parses numbers like "3" or "3/12"
+pub fn parse_number(value: &str) -> Option<u16> {
+    parse_number_pair(value).0
+}
+
+/// Parses a number and the optional total like "3/12"
+fn parse_number_pair(value: &str) -> (Option<u16>, Option<u16>) {
+    let mut parts = value.split('/');
+    let number = parts.next().and_then(|n| n.trim().parse().ok());
+    let total = parts.next().and_then(|n| n.trim().parse().ok());
+    (number, total)
+}
+
+/// Parses flags like "1" or "true"
+fn parse_flag(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+/// Parses the year of dates like "2001" or "2001-05-12"
+pub fn parse_year(value: &str) -> Option<i32> {
+    let digits: String = value
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+fn read_u32_le(data: &mut &[u8]) -> std::io::Result<u32> {
+    let bytes = take(data, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> std::io::Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(invalid_data("Unexpected end of tag"));
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected_tags(format: &str) -> RawTags {
+        RawTags {
+            album: Some(String::from("The Foos are Back")),
+            album_artist: Some(String::from("Various Foos")),
+            artist: Some(String::from("The Foos")),
+            comment: Some(String::from("Recorded live")),
+            compilation: false,
+            composer: Some(String::from("Foo Bar")),
+            disc_number: Some(1),
+            genre: Some(String::from("Rock")),
+            title: Some(format!("Foo de Foo ({})", format)),
+            total_discs: Some(2),
+            total_tracks: Some(12),
+            track_number: Some(3),
+            year: Some(1999),
+        }
+    }
+
+    #[test]
+    fn test_mp3() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/id3.mp3")),
+            Ok(expected_tags("MP3"))
+        );
+    }
+
+    #[test]
+    fn test_flac() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/flac.flac")),
+            Ok(expected_tags("FLAC"))
+        );
+    }
+
+    #[test]
+    fn test_mp4() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/mp4.m4a")),
+            Ok(expected_tags("MP4"))
+        );
+    }
+
+    #[test]
+    fn test_ogg_vorbis() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/vorbis.ogg")),
+            Ok(expected_tags("Vorbis"))
+        );
+    }
+
+    #[test]
+    fn test_opus() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/opus.opus")),
+            Ok(expected_tags("Opus"))
+        );
+    }
+
+    #[test]
+    fn test_wav() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/wave.wav")),
+            Ok(expected_tags("WAV"))
+        );
+    }
+
+    #[test]
+    fn test_aiff() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/aiff.aiff")),
+            Ok(expected_tags("AIFF"))
+        );
+    }
+
+    #[test]
+    fn test_wavpack() {
+        assert_eq!(
+            read_tags(Path::new("testfiles/wavpack.wv")),
+            Ok(expected_tags("WavPack"))
+        );
+    }
+
+    #[test]
+    fn test_untagged_mp3() {
+        assert!(read_tags(Path::new("testfiles/foo.mp3")).is_err());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("3"), Some(3));
+        assert_eq!(parse_number(" 3/12"), Some(3));
+        assert_eq!(parse_number("x"), None);
+        assert_eq!(parse_number_pair("3/12"), (Some(3), Some(12)));
+        assert_eq!(parse_number_pair("3"), (Some(3), None));
+    }
+
+    #[test]
+    fn test_set_from_path() {
+        let mut tags = RawTags {
+            artist: Some(String::from("Tagged Artist")),
+            ..RawTags::default()
+        };
+        let captures = vec![
+            (Placeholder::Artist, String::from("Path Artist")),
+            (Placeholder::Album, String::from("Album")),
+            (Placeholder::Track, String::from("07")),
+            (Placeholder::Title, String::from("Title")),
+        ];
+
+        // the tags take precedence over the path
+        assert_eq!(
+            tags.set_from_path(captures.clone(), false),
+            vec![Placeholder::Album, Placeholder::Track, Placeholder::Title]
+        );
+        assert_eq!(tags.artist, Some(String::from("Tagged Artist")));
+        assert_eq!(tags.album, Some(String::from("Album")));
+        assert_eq!(tags.track_number, Some(7));
+        assert_eq!(tags.title, Some(String::from("Title")));
+
+        assert_eq!(
+            tags.set_from_path(captures, true),
+            vec![Placeholder::Artist]
+        );
+        assert_eq!(tags.artist, Some(String::from("Path Artist")));
+    }
+
+    #[test]
+    fn test_complete_with() {
+        let tags = RawTags {
+            artist: Some(String::from("The Foos")),
+            track_number: Some(3),
+            ..RawTags::default()
+        };
+        let format_tags = RawTags {
+            artist: Some(String::from("Foos")),
+            compilation: true,
+            genre: Some(String::from("Rock")),
+            ..RawTags::default()
+        };
+
+        // audiotags' values win
+        let tags = tags.complete_with(format_tags);
+        assert_eq!(tags.artist, Some(String::from("The Foos")));
+        assert_eq!(tags.track_number, Some(3));
+        assert_eq!(tags.genre, Some(String::from("Rock")));
+        assert!(tags.compilation);
+    }
+
+    #[test]
+    fn test_compilation() {
+        let fields = vec![(String::from("Compilation"), String::from("1"))];
+        assert!(RawTags::from_fields(fields).compilation);
+        let fields = vec![(String::from("COMPILATION"), String::from("0"))];
+        assert!(!RawTags::from_fields(fields).compilation);
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2001"), Some(2001));
+        assert_eq!(parse_year("2001-05-12"), Some(2001));
+        assert_eq!(parse_year("unknown"), None);
+    }
+}
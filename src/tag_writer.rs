@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use id3::TagLike;
+
+use crate::tag_reader::RawTags;
+use crate::template::Placeholder;
+
+/// Writes the given fields of the tags to a music file, keeping all other tags.
+/// The tag format is chosen by the file's extension, just like when reading, and each
+/// file is saved once. Ogg Vorbis, Opus, and WavPack files can be read, but not written.
+pub fn write_tags(path: &Path, tags: &RawTags, fields: &[Placeholder]) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        // WAV and AIFF files carry the ID3 tag in a chunk of their container
+        "mp3" | "wav" | "aif" | "aiff" => write_id3(path, tags, fields).map_err(|e| e.to_string()),
+        "flac" => write_flac(path, tags, fields).map_err(|e| e.to_string()),
+        "m4a" | "m4b" | "m4p" | "m4v" => write_mp4(path, tags, fields).map_err(|e| e.to_string()),
+        _ => Err(format!("Cannot write tags to .{} files", extension)),
+    }
+}
+
+fn write_id3(path: &Path, tags: &RawTags, fields: &[Placeholder]) -> id3::Result<()> {
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+        Err(err) => return Err(err),
+    };
+
+    for field in fields {
+        match field {
+            Placeholder::Album => tag.set_album(tags.album.clone().unwrap_or_default()),
+            Placeholder::AlbumArtist => {
+                tag.set_album_artist(tags.album_artist.clone().unwrap_or_default())
+            }
+            Placeholder::Artist => tag.set_artist(tags.artist.clone().unwrap_or_default()),
+            Placeholder::Composer => {
+                tag.set_text("TCOM", tags.composer.clone().unwrap_or_default())
+            }
+            Placeholder::Disc => tag.set_disc(tags.disc_number.unwrap_or_default().into()),
+            Placeholder::DiscTotal => {
+                tag.set_total_discs(tags.total_discs.unwrap_or_default().into())
+            }
+            Placeholder::Genre => tag.set_genre(tags.genre.clone().unwrap_or_default()),
+            Placeholder::Title => tag.set_title(tags.title.clone().unwrap_or_default()),
+            Placeholder::Track => tag.set_track(tags.track_number.unwrap_or_default().into()),
+            Placeholder::TrackTotal => {
+                tag.set_total_tracks(tags.total_tracks.unwrap_or_default().into())
+            }
+            Placeholder::Year => tag.set_year(tags.year.unwrap_or_default()),
+            Placeholder::Format => {}
+        }
+    }
+
+    // always ID3v2.3, which is what most players understand, whatever version was read
+    tag.write_to_path(path, id3::Version::Id3v23)
+}
+
+fn write_flac(path: &Path, tags: &RawTags, fields: &[Placeholder]) -> metaflac::Result<()> {
+    let mut tag = metaflac::Tag::read_from_path(path)?;
+
+    for field in fields {
+        let (key, value) = match field {
+            Placeholder::Album => ("ALBUM", tags.album.clone()),
+            Placeholder::AlbumArtist => ("ALBUMARTIST", tags.album_artist.clone()),
+            Placeholder::Artist => ("ARTIST", tags.artist.clone()),
+            Placeholder::Composer => ("COMPOSER", tags.composer.clone()),
+            Placeholder::Disc => ("DISCNUMBER", tags.disc_number.map(|n| n.to_string())),
+            Placeholder::DiscTotal => ("DISCTOTAL", tags.total_discs.map(|n| n.to_string())),
+            Placeholder::Genre => ("GENRE", tags.genre.clone()),
+            Placeholder::Title => ("TITLE", tags.title.clone()),
+            Placeholder::Track => ("TRACKNUMBER", tags.track_number.map(|n| n.to_string())),
+            Placeholder::TrackTotal => ("TRACKTOTAL", tags.total_tracks.map(|n| n.to_string())),
+            Placeholder::Year => ("DATE", tags.year.map(|n| n.to_string())),
+            Placeholder::Format => continue,
+        };
+        tag.set_vorbis(key, vec![value.unwrap_or_default()]);
+    }
+
+    tag.save()
+}
+
+fn write_mp4(path: &Path, tags: &RawTags, fields: &[Placeholder]) -> mp4ameta::Result<()> {
+    let mut tag = mp4ameta::Tag::read_from_path(path)?;
+
+    for field in fields {
+        match field {
+            Placeholder::Album => tag.set_album(tags.album.clone().unwrap_or_default()),
+            Placeholder::AlbumArtist => {
+                tag.set_album_artist(tags.album_artist.clone().unwrap_or_default())
+            }
+            Placeholder::Artist => tag.set_artist(tags.artist.clone().unwrap_or_default()),
+            Placeholder::Composer => tag.set_composer(tags.composer.clone().unwrap_or_default()),
+            Placeholder::Disc => tag.set_disc_number(tags.disc_number.unwrap_or_default()),
+            Placeholder::DiscTotal => tag.set_total_discs(tags.total_discs.unwrap_or_default()),
+            Placeholder::Genre => tag.set_genre(tags.genre.clone().unwrap_or_default()),
+            Placeholder::Title => tag.set_title(tags.title.clone().unwrap_or_default()),
+            Placeholder::Track => tag.set_track_number(tags.track_number.unwrap_or_default()),
+            Placeholder::TrackTotal => tag.set_total_tracks(tags.total_tracks.unwrap_or_default()),
+            Placeholder::Year => tag.set_year(tags.year.unwrap_or_default().to_string()),
+            Placeholder::Format => {}
+        }
+    }
+
+    tag.write_to_path(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag_reader;
+    use crate::test_util::TempDir;
+
+    fn write_and_read(fixture: &str) -> RawTags {
+        let temp_dir = TempDir::new("tag-writer");
+        let path = temp_dir.path().join(fixture);
+        std::fs::copy(Path::new("testfiles").join(fixture), &path).unwrap();
+
+        let tags = RawTags {
+            genre: Some(String::from("Jazz")),
+            title: Some(String::from("Bar de Bar")),
+            track_number: Some(4),
+            ..RawTags::default()
+        };
+        let fields = [Placeholder::Title, Placeholder::Track, Placeholder::Genre];
+        write_tags(&path, &tags, &fields).unwrap();
+        tag_reader::read_tags(&path).unwrap()
+    }
+
+    #[test]
+    fn test_write_tags() {
+        for fixture in ["id3.mp3", "flac.flac", "mp4.m4a", "wave.wav", "aiff.aiff"] {
+            let tags = write_and_read(fixture);
+            assert_eq!(tags.title, Some(String::from("Bar de Bar")), "{}", fixture);
+            assert_eq!(tags.track_number, Some(4), "{}", fixture);
+            assert_eq!(tags.genre, Some(String::from("Jazz")), "{}", fixture);
+            // the other tags are kept
+            assert_eq!(tags.album, Some(String::from("The Foos are Back")));
+            assert_eq!(tags.composer, Some(String::from("Foo Bar")));
+        }
+    }
+
+    #[test]
+    fn test_untagged_mp3() {
+        let temp_dir = TempDir::new("tag-writer-untagged");
+        let path = temp_dir.path().join("foo.mp3");
+        std::fs::copy("testfiles/foo.mp3", &path).unwrap();
+        let tags = RawTags {
+            title: Some(String::from("Bar de Bar")),
+            ..RawTags::default()
+        };
+
+        write_tags(&path, &tags, &[Placeholder::Title]).unwrap();
+        let written = tag_reader::read_tags(&path).unwrap();
+        assert_eq!(written.title, Some(String::from("Bar de Bar")));
+    }
+
+    #[test]
+    fn test_unsupported_formats() {
+        for fixture in ["vorbis.ogg", "opus.opus", "wavpack.wv"] {
+            let path = Path::new("testfiles").join(fixture);
+            assert!(
+                write_tags(&path, &RawTags::default(), &[]).is_err(),
+                "{}",
+                fixture
+            );
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::fmt;
+use std::fmt::Formatter;
+
+/// The tags a template can refer to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placeholder {
+    Album,
+    AlbumArtist,
+    Artist,
+    Composer,
+    Disc,
+    /// The number of discs of the album
+    DiscTotal,
+    Format,
+    Genre,
+    Title,
+    Track,
+    /// The number of tracks of the disc
+    TrackTotal,
+    Year,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 12] = [
+        Placeholder::Album,
+        Placeholder::AlbumArtist,
+        Placeholder::Artist,
+        Placeholder::Composer,
+        Placeholder::Disc,
+        Placeholder::DiscTotal,
+        Placeholder::Format,
+        Placeholder::Genre,
+        Placeholder::Title,
+        Placeholder::Track,
+        Placeholder::TrackTotal,
+        Placeholder::Year,
+    ];
+
+    /// Returns the name used inside the curly braces of a template
+    pub fn name(&self) -> &'static str {
+        match self {
+            Placeholder::Album => "album",
+            Placeholder::AlbumArtist => "albumartist",
+            Placeholder::Artist => "artist",
+            Placeholder::Composer => "composer",
+            Placeholder::Disc => "disc",
+            Placeholder::DiscTotal => "disctotal",
+            Placeholder::Format => "format",
+            Placeholder::Genre => "genre",
+            Placeholder::Title => "title",
+            Placeholder::Track => "track",
+            Placeholder::TrackTotal => "tracktotal",
+            Placeholder::Year => "year",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Placeholder> {
+        Placeholder::ALL.iter().copied().find(|p| p.name() == name)
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Placeholder::Disc
+                | Placeholder::DiscTotal
+                | Placeholder::Track
+                | Placeholder::TrackTotal
+        )
+    }
+}
+
+/// The value a placeholder is replaced with
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A number, zero-padded to `width` digits unless the template specifies its own width
+    Number {
+        value: u16,
+        width: usize,
+    },
+    Text(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field {
+        placeholder: Placeholder,
+        width: Option<usize>,
+    },
+}
+
+/// A parsed name template like `"{disc:02}-{track:02} {title}"`
+#[derive(Debug)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parses a template consisting of literal text and placeholders in curly braces.
+    /// Numeric placeholders may carry a width (`{track:02}`), literal braces are written
+    /// as `{{` and `}}`.
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            None => return Err(format!("Unclosed placeholder \"{{{}\"", field)),
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Template::parse_field(&field)?);
+                }
+                '}' => return Err(String::from("Unmatched \"}\"")),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    fn parse_field(field: &str) -> Result<Segment, String> {
+        let (name, width) = match field.split_once(':') {
+            None => (field, None),
+            Some((name, width)) => (name, Some(width)),
+        };
+
+        let placeholder = Placeholder::from_name(name.trim())
+            .ok_or_else(|| format!("Unknown placeholder \"{{{}}}\"", field))?;
+
+        let width = match width {
+            None => None,
+            Some(width) => {
+                if !placeholder.is_numeric() {
+                    return Err(format!(
+                        "Only numeric placeholders can have a width: \"{{{}}}\"",
+                        field
+                    ));
+                }
+                match width.parse::<usize>() {
+                    Ok(width) => Some(width),
+                    Err(_) => return Err(format!("Cannot parse width \"{{{}}}\"", field)),
+                }
+            }
+        };
+
+        Ok(Segment::Field { placeholder, width })
+    }
+
+    /// Replaces the placeholders with the values returned by `lookup`.
+    /// Returns the first placeholder `lookup` couldn't provide a value for.
+    /// A placeholder replaced with the empty string takes the text separating it from the
+    /// next placeholder along, or from the previous one if it is the last, so that
+    /// `"{disc}-{track}"` doesn't start with "-" without a disc number. Separators
+    /// containing a "/" are always kept.
+    pub fn render<F>(&self, lookup: F) -> Result<String, Placeholder>
+    where
+        F: Fn(Placeholder) -> Option<Value>,
+    {
+        let mut parts = Vec::new();
+        for segment in &self.segments {
+            let part = match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Field { placeholder, width } => match lookup(*placeholder) {
+                    None => return Err(*placeholder),
+                    Some(Value::Text(text)) => text,
+                    Some(Value::Number {
+                        value,
+                        width: default_width,
+                    }) => {
+                        let width = width.unwrap_or(default_width);
+                        format!("{:0width$}", value, width = width)
+                    }
+                },
+            };
+            parts.push(part);
+        }
+
+        let is_field = |i: usize| matches!(self.segments.get(i), Some(Segment::Field { .. }));
+        let is_separator = |i: usize| {
+            matches!(&self.segments[i], Segment::Literal(text) if !text.contains('/'))
+                && i > 0
+                && is_field(i - 1)
+                && is_field(i + 1)
+        };
+        let mut dropped = vec![false; parts.len()];
+        for i in 0..parts.len() {
+            if !is_field(i) || !parts[i].is_empty() {
+                continue;
+            }
+            if i + 1 < parts.len() && is_separator(i + 1) {
+                dropped[i + 1] = true;
+            } else if i > 0 && is_separator(i - 1) && !(i + 2..parts.len()).any(is_field) {
+                dropped[i - 1] = true;
+            }
+        }
+
+        Ok(parts
+            .into_iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| !dropped)
+            .map(|(part, _)| part)
+            .collect())
+    }
+
+    /// Builds a regular expression matching the names the template renders to, with one
+    /// group per placeholder. Numeric placeholders only match digits, and no placeholder
+    /// matches a "/". Returns the expression and the placeholders in the order of their groups.
+    pub fn to_regex(&self) -> (String, Vec<Placeholder>) {
+        let mut regex = String::new();
+        let mut placeholders = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => regex.push_str(&regex::escape(text)),
+                Segment::Field { placeholder, .. } => {
+                    regex.push_str(if placeholder.is_numeric() {
+                        r"(\d+)"
+                    } else {
+                        r"([^/]+?)"
+                    });
+                    placeholders.push(*placeholder);
+                }
+            }
+        }
+
+        (regex, placeholders)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(placeholder: Placeholder) -> Option<Value> {
+        match placeholder {
+            Placeholder::Album => Some(Value::Text(String::from("Album"))),
+            Placeholder::AlbumArtist => Some(Value::Text(String::from("Album Artist"))),
+            Placeholder::Artist => Some(Value::Text(String::from("Artist"))),
+            Placeholder::Composer => None,
+            Placeholder::Disc => None,
+            Placeholder::DiscTotal => None,
+            Placeholder::Format => Some(Value::Text(String::from("FLAC"))),
+            Placeholder::Genre => None,
+            Placeholder::Title => Some(Value::Text(String::from("Title"))),
+            Placeholder::Track => Some(Value::Number { value: 7, width: 3 }),
+            Placeholder::TrackTotal => Some(Value::Number {
+                value: 12,
+                width: 3,
+            }),
+            Placeholder::Year => Some(Value::Text(String::from("1999"))),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let template = Template::parse("{track:02} {title}").unwrap();
+        assert_eq!(
+            template.segments,
+            vec![
+                Segment::Field {
+                    placeholder: Placeholder::Track,
+                    width: Some(2)
+                },
+                Segment::Literal(String::from(" ")),
+                Segment::Field {
+                    placeholder: Placeholder::Title,
+                    width: None
+                },
+            ]
+        );
+        assert_eq!(template.to_string(), "{track:02} {title}");
+
+        assert_eq!(
+            Template::parse("{{{album}}}").unwrap().segments,
+            vec![
+                Segment::Literal(String::from("{")),
+                Segment::Field {
+                    placeholder: Placeholder::Album,
+                    width: None
+                },
+                Segment::Literal(String::from("}")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("{foo}").is_err());
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("title}").is_err());
+        assert!(Template::parse("{title:02}").is_err());
+        assert!(Template::parse("{track:xx}").is_err());
+    }
+
+    #[test]
+    fn test_to_regex() {
+        assert_eq!(
+            Template::parse("{artist}/{track:02}. {title}")
+                .unwrap()
+                .to_regex(),
+            (
+                String::from(r"([^/]+?)/(\d+)\. ([^/]+?)"),
+                vec![Placeholder::Artist, Placeholder::Track, Placeholder::Title]
+            )
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            Template::parse("{artist} - {album} - {track} {title}")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("Artist - Album - 007 Title"))
+        );
+        assert_eq!(
+            Template::parse("{track:2} {title}").unwrap().render(lookup),
+            Ok(String::from("07 Title"))
+        );
+        assert_eq!(
+            Template::parse("{track:0} {title}").unwrap().render(lookup),
+            Ok(String::from("7 Title"))
+        );
+        assert_eq!(
+            Template::parse("{albumartist}/{year} - {album}")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("Album Artist/1999 - Album"))
+        );
+        assert_eq!(
+            Template::parse("{album} [{format}]")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("Album [FLAC]"))
+        );
+        assert_eq!(
+            Template::parse("{track:02} of {tracktotal:02} {title}")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("07 of 12 Title"))
+        );
+        assert_eq!(
+            Template::parse("{disc}-{track} {title}")
+                .unwrap()
+                .render(lookup),
+            Err(Placeholder::Disc)
+        );
+    }
+
+    #[test]
+    fn test_render_empty_values() {
+        let lookup = |placeholder| match placeholder {
+            Placeholder::Disc | Placeholder::Genre => Some(Value::Text(String::new())),
+            placeholder => lookup(placeholder),
+        };
+
+        // the separator goes along with a missing disc number
+        assert_eq!(
+            Template::parse("{disc:02}-{track:02} {title}")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("07 Title"))
+        );
+        assert_eq!(
+            Template::parse("{artist} - {genre} - {title}")
+                .unwrap()
+                .render(lookup),
+            Ok(String::from("Artist - Title"))
+        );
+        // the last placeholder takes the separator before it
+        assert_eq!(
+            Template::parse("{title} - {genre}").unwrap().render(lookup),
+            Ok(String::from("Title"))
+        );
+        // literal text that doesn't separate placeholders is kept
+        assert_eq!(
+            Template::parse("Disc {disc}").unwrap().render(lookup),
+            Ok(String::from("Disc "))
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::Config;
+
+/// Which spelling is used for letters a language writes in ASCII its own way
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Locale {
+    /// Strips the accents, e.g. "ä" becomes "a"
+    #[default]
+    Default,
+    /// Writes umlauts the German way, e.g. "ä" becomes "ae"
+    German,
+}
+
+impl Locale {
+    pub fn from_name(name: &str) -> Option<Locale> {
+        match name {
+            "default" => Some(Locale::Default),
+            "de" => Some(Locale::German),
+            _ => None,
+        }
+    }
+
+    /// Returns the locale's own transliteration of a character, if it has one
+    fn transliterate(&self, c: char) -> Option<&'static str> {
+        match self {
+            Locale::Default => None,
+            Locale::German => match c {
+                'ä' => Some("ae"),
+                'ö' => Some("oe"),
+                'ü' => Some("ue"),
+                'Ä' => Some("Ae"),
+                'Ö' => Some("Oe"),
+                'Ü' => Some("Ue"),
+                'ß' => Some("ss"),
+                'ẞ' => Some("SS"),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Transliterates a text to ASCII, e.g. "é" to "e", Cyrillic and Greek to Latin letters,
+/// and Chinese or Japanese to their romanization where possible. The text is composed (NFC)
+/// first, so that a letter followed by a combining accent is looked up as a single letter.
+pub fn to_ascii(text: &str, locale: Locale) -> String {
+    let mut result = String::new();
+    for c in text.nfc() {
+        match locale.transliterate(c) {
+            Some(ascii) => result.push_str(ascii),
+            None if c.is_ascii() => result.push(c),
+            None => {
+                let ascii = deunicode::deunicode_char(c).unwrap_or("_");
+                // Chinese characters become words of their own, ending with a blank
+                if ascii.ends_with(' ') && result.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+                    result.push(' ');
+                }
+                result.push_str(ascii);
+            }
+        }
+    }
+    result
+}
+
+/// Transliterates a file or directory name if `--ascii` is given
+pub fn transliterate_name(name: &str, config: &Config) -> String {
+    match config.ascii {
+        None => name.to_string(),
+        Some(locale) => to_ascii(name, locale),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(to_ascii("Motörhead", Locale::Default), "Motorhead");
+        assert_eq!(to_ascii("Motörhead", Locale::German), "Motoerhead");
+        // "ö" written as "o" and a combining diaeresis, like macOS does
+        assert_eq!(to_ascii("Moto\u{308}rhead", Locale::German), "Motoerhead");
+        assert_eq!(to_ascii("Moto\u{308}rhead", Locale::Default), "Motorhead");
+        assert_eq!(to_ascii("Straße", Locale::Default), "Strasse");
+        assert_eq!(
+            to_ascii("Où est la plage", Locale::German),
+            "Ou est la plage"
+        );
+        assert_eq!(to_ascii("Кино", Locale::Default), "Kino");
+        assert_eq!(to_ascii("Ελλάδα", Locale::Default), "Ellada");
+        assert_eq!(
+            to_ascii("東京の夜", Locale::Default).trim(),
+            "Dong Jing no Ye"
+        );
+    }
+}
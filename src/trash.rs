@@ -0,0 +1,256 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::journal::{Action, Journal};
+use crate::rename_plan::PlannedAction;
+use crate::report::{OutputFormat, Report};
+use crate::util;
+
+/// The file in the trash directory remembering when each file has been trashed. Moving a
+/// file keeps its modification time, so that cannot tell.
+const INDEX_NAME: &str = ".mp3rename-trash.jsonl";
+
+/// One line of the trash index
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TrashEntry {
+    path: PathBuf,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+}
+
+/// Returns where a file is moved to in the trash directory, keeping its path relative to the
+/// start directory. If a file of that name has been trashed before, a number is appended.
+pub fn trash_path(trash_dir: &Path, path: &Path, config: &Config) -> PathBuf {
+    let relative_path = path
+        .strip_prefix(&config.start_dir)
+        .ok()
+        .filter(|rest| !rest.as_os_str().is_empty())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path);
+    let trash_path = trash_dir.join(relative_path);
+    if !trash_path.exists() {
+        return trash_path;
+    }
+
+    let stem = trash_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = trash_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| trash_path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Moves a file into the trash directory, creating the directories it is kept in
+pub fn move_to_trash(path: &Path, trash_path: &Path, trash_dir: &Path) -> std::io::Result<()> {
+    if let Some(dir) = trash_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    util::move_file_or_directory(path, trash_path)?;
+
+    let mut index = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(trash_dir.join(INDEX_NAME))?;
+    let entry = TrashEntry {
+        path: trash_path.to_path_buf(),
+        timestamp: now(),
+    };
+    writeln!(index, "{}", serde_json::to_string(&entry)?)
+}
+
+/// Removes the files that have been moved to the trash directory more than `days` days ago.
+/// Files mp3rename hasn't trashed itself are left alone.
+pub fn purge(
+    trash_dir: &Path,
+    days: u64,
+    config: &Config,
+    journal: &mut Journal,
+    report: &mut Report,
+) {
+    let index_path = trash_dir.join(INDEX_NAME);
+    let entries = match read_index(&index_path) {
+        Ok(entries) => entries,
+        // nothing has been trashed yet
+        Err(_) => return,
+    };
+
+    let oldest = now().saturating_sub(days.saturating_mul(24 * 60 * 60));
+    let mut kept = Vec::new();
+    report.enter_directory(trash_dir);
+    for entry in entries {
+        if !entry.path.exists() {
+            // restored with `undo` or removed by hand
+            continue;
+        }
+        if entry.timestamp > oldest {
+            kept.push(entry);
+            continue;
+        }
+
+        progress!(config, "Purging \"{}\"", entry.path.to_string_lossy());
+        report.record(&PlannedAction {
+            action: Action::Remove,
+            old_path: entry.path.clone(),
+            new_path: None,
+            tags: None,
+        });
+        if config.dry_run {
+            kept.push(entry);
+            continue;
+        }
+        match fs::remove_file(&entry.path) {
+            Ok(()) => {
+                journal.record(Action::Remove, &entry.path, None);
+                remove_empty_dirs(entry.path.parent(), trash_dir);
+            }
+            Err(err) => {
+                eprintln!(
+                    "Couldn't remove \"{}\": {}",
+                    entry.path.to_string_lossy(),
+                    err
+                );
+                kept.push(entry);
+            }
+        }
+    }
+
+    if !config.dry_run {
+        if let Err(err) = write_index(&index_path, &kept) {
+            eprintln!(
+                "Couldn't write the trash index \"{}\": {}",
+                index_path.to_string_lossy(),
+                err
+            );
+        }
+    }
+}
+
+/// Removes the directories the purged files have been kept in, up to the trash directory
+fn remove_empty_dirs(dir: Option<&Path>, trash_dir: &Path) {
+    let mut dir = dir;
+    while let Some(path) = dir {
+        // this fails for directories that aren't empty, which is what we want
+        if path == trash_dir || !path.starts_with(trash_dir) || fs::remove_dir(path).is_err() {
+            break;
+        }
+        dir = path.parent();
+    }
+}
+
+fn read_index(path: &Path) -> std::io::Result<Vec<TrashEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn write_index(path: &Path, entries: &[TrashEntry]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_trash_and_purge() {
+        let temp_dir = TempDir::new("trash");
+        let dir = temp_dir.path();
+        let start_dir = dir.join("music");
+        let trash_dir = dir.join("trash");
+        fs::create_dir_all(start_dir.join("album")).unwrap();
+        let config = Config {
+            start_dir: start_dir.clone(),
+            journal: Some(dir.join("journal.jsonl")),
+            ..Config::default()
+        };
+
+        for _ in 0..2 {
+            let path = start_dir.join("album").join("cover.jpg");
+            fs::write(&path, "foo").unwrap();
+            let trash_path = trash_path(&trash_dir, &path, &config);
+            move_to_trash(&path, &trash_path, &trash_dir).unwrap();
+            assert!(!path.exists());
+        }
+        // the relative path is kept, and nothing is overwritten
+        assert!(trash_dir.join("album").join("cover.jpg").exists());
+        assert!(trash_dir.join("album").join("cover (2).jpg").exists());
+
+        // the files have just been trashed
+        let mut journal = Journal::new(&config);
+        let mut report = Report::default();
+        purge(&trash_dir, 1, &config, &mut journal, &mut report);
+        assert!(trash_dir.join("album").join("cover.jpg").exists());
+
+        purge(&trash_dir, 0, &config, &mut journal, &mut report);
+        assert!(!trash_dir.join("album").exists());
+        assert_eq!(read_index(&trash_dir.join(INDEX_NAME)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_purge_with_csv_output() {
+        let temp_dir = TempDir::new("purge");
+        let dir = temp_dir.path();
+        let trash_dir = dir.join("trash");
+        let config = Config {
+            start_dir: dir.to_path_buf(),
+            journal: Some(dir.join("journal.jsonl")),
+            output_format: OutputFormat::Csv,
+            ..Config::default()
+        };
+
+        let path = dir.join("cover.jpg");
+        fs::write(&path, "foo").unwrap();
+        move_to_trash(&path, &trash_path(&trash_dir, &path, &config), &trash_dir).unwrap();
+
+        // the purge shows up in the report instead of being printed, and days far in the
+        // past don't overflow
+        let mut journal = Journal::new(&config);
+        let mut report = Report::new(config.output_format);
+        purge(&trash_dir, u64::MAX, &config, &mut journal, &mut report);
+        assert!(trash_dir.join("cover.jpg").exists());
+        purge(&trash_dir, 0, &config, &mut journal, &mut report);
+        assert!(!trash_dir.join("cover.jpg").exists());
+
+        let mut out = Vec::new();
+        report.write(&mut out, config.output_format).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[1..],
+            [format!(
+                "{},remove,cover.jpg,,,",
+                trash_dir.to_string_lossy()
+            )]
+        );
+    }
+}
@@ -1,10 +1,13 @@
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::{cmp, fs};
 
 use regex::Regex;
+use unicode_normalization::UnicodeNormalization as _;
+use unicode_segmentation::UnicodeSegmentation;
 use walkdir::WalkDir;
 
 use crate::config::Config;
+use crate::extensions::Extensions;
 
 /// Returns the list of directories.
 pub fn get_list_of_dirs(config: &Config) -> Vec<walkdir::DirEntry> {
@@ -27,75 +30,217 @@ pub fn get_list_of_dirs(config: &Config) -> Vec<walkdir::DirEntry> {
         .collect()
 }
 
-pub fn is_music_file(entry: &fs::DirEntry) -> bool {
+pub fn is_music_file(entry: &fs::DirEntry, extensions: &Extensions) -> bool {
     let path = entry.path();
     let file_name = path.to_str();
     match file_name {
         None => false,
-        Some(file_name) => is_music_filename(file_name),
+        Some(file_name) => is_music_filename(file_name, extensions),
     }
 }
 
 /// Checks if a name's extension is in a list of music file extensions
-pub fn is_music_filename(file_name: &str) -> bool {
-    let music_extensions = vec![".mp3", ".flac", ".m4a", ".m4b", ".m4p", ".m4v"];
-    let file_name = file_name.to_lowercase();
-    for ext in music_extensions {
-        if file_name.ends_with(ext) {
-            return true;
-        }
-    }
-
-    false
+pub fn is_music_filename(file_name: &str, extensions: &Extensions) -> bool {
+    extensions.matches(file_name)
 }
 
-pub fn sanitize_file_or_directory_name(filename: &str) -> String {
-    let mut name = filename.replace('$', "_");
-    name = name.replace("???", "Fragezeichen");
-
-    // Windows doesn't like any of the following characters
-    name = name.replace('\\', "");
-    name = name.replace('/', " & ");
-    name = name.replace(':', " -");
-    name = name.replace('*', "_");
-    name = name.replace('?', "");
-    name = name.replace('\"', "");
-    name = name.replace('<', "");
-    name = name.replace('>', "");
-    name = name.replace('|', ", ");
+/// Makes a name fit for the target file system given by the sanitization profile,
+/// after applying the user's replacements
+pub fn sanitize_file_or_directory_name(filename: &str, config: &Config) -> String {
+    let profile = config.sanitize_profile;
+    let mut name = filename.to_string();
+    for (from, to) in &config.replacements {
+        name = name.replace(from, to);
+    }
+
+    // replace the characters the target file system doesn't like
+    name = name
+        .chars()
+        .map(|c| match profile.replacement(c) {
+            Some(replacement) => replacement.to_string(),
+            None => c.to_string(),
+        })
+        .collect();
 
     // now we added blanks, let's handle the ones in the beginning and at the end
     name = name.trim().to_string();
 
-    // remove dots at the start
+    // remove dots at the start, as they hide files
     let re = Regex::new(r"^[.]*").unwrap();
     name = re.replace_all(&name, "").to_string();
 
     // remove dots at the end
-    let re = Regex::new(r"[.]*$").unwrap();
-    name = re.replace_all(&name, "").to_string();
+    if profile.strips_trailing_dots() {
+        let re = Regex::new(r"[.\s]*$").unwrap();
+        name = re.replace_all(&name, "").to_string();
+    }
 
     // replace whitespace with only one blank each
     let re = Regex::new(r"\s+").unwrap();
     name = re.replace_all(&name, " ").to_string();
 
     // remove any blanks at the name's start or end
-    name.trim().to_string()
+    name = name.trim().to_string();
+
+    // device names like "CON" cannot be used as names, not even with an extension,
+    // so the device name itself is changed
+    if profile.is_reserved_name(&name) {
+        let stem = &name[..name.find('.').unwrap_or(name.len())];
+        name.insert(stem.trim_end().len(), '_');
+    }
+    name
+}
+
+/// What the length limit of names counts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LengthUnit {
+    /// Characters, as most file systems limit names to 255 characters
+    #[default]
+    Chars,
+    /// Bytes of the UTF-8 encoded name, as e.g. ext4 limits names to 255 bytes
+    Bytes,
+}
+
+impl LengthUnit {
+    pub fn from_name(name: &str) -> Option<LengthUnit> {
+        match name {
+            "chars" => Some(LengthUnit::Chars),
+            "bytes" => Some(LengthUnit::Bytes),
+            _ => None,
+        }
+    }
+
+    pub fn length(&self, text: &str) -> usize {
+        match self {
+            LengthUnit::Chars => text.chars().count(),
+            LengthUnit::Bytes => text.len(),
+        }
+    }
+}
+
+/// The Unicode normalization form of the names. macOS writes names decomposed (NFD), while
+/// most other systems write them composed (NFC).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnicodeNormalization {
+    /// Keep the names as they are
+    #[default]
+    None,
+    /// Composed, e.g. "é" as a single code point
+    Nfc,
+    /// Decomposed, e.g. "é" as "e" followed by a combining accent
+    Nfd,
+}
+
+impl UnicodeNormalization {
+    pub fn from_name(name: &str) -> Option<UnicodeNormalization> {
+        match name {
+            "none" => Some(UnicodeNormalization::None),
+            "nfc" => Some(UnicodeNormalization::Nfc),
+            "nfd" => Some(UnicodeNormalization::Nfd),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            UnicodeNormalization::None => name.to_string(),
+            UnicodeNormalization::Nfc => name.nfc().collect(),
+            UnicodeNormalization::Nfd => name.nfd().collect(),
+        }
+    }
+}
+
+/// Checks if a file or directory keeps its name. Names only differing in their Unicode
+/// normalization are the same unless names are kept as they are.
+pub fn is_same_path(old_path: &Path, new_path: &Path, config: &Config) -> bool {
+    if old_path == new_path {
+        return true;
+    }
+    let normalization = config.unicode_normalization;
+    if normalization == UnicodeNormalization::None || old_path.parent() != new_path.parent() {
+        return false;
+    }
+    match (old_path.file_name(), new_path.file_name()) {
+        (Some(old_name), Some(new_name)) => {
+            normalization.apply(&old_name.to_string_lossy())
+                == normalization.apply(&new_name.to_string_lossy())
+        }
+        _ => false,
+    }
+}
+
+const ELLIPSIS: &str = "…";
+
+/// Shortens a file name so that it (together with the extension) fits in the length
+/// given with `--limit-length`
+pub fn shorten_names(path: &Path, name: &str, config: &Config) -> String {
+    shorten_name(path, name, config.name_length as usize, config)
 }
 
 /// Shortens a file name so that it (together with the extension) fits in a given length
 /// Combines the path's extension with the stem from the name.
-pub fn shorten_names(path: &Path, name: &str, config: &Config) -> String {
-    let (extension, extension_len): (String, usize) = get_extension(path);
+/// Characters made of several code points like "é" written as "e" and a combining accent
+/// are never split, and the name is cut at a word boundary if there is one.
+pub fn shorten_name(path: &Path, name: &str, max_len: usize, config: &Config) -> String {
+    let (extension, _): (String, usize) = get_extension(path);
     let stem = get_name_stem(name, &extension);
+    shorten_stem(&stem, extension, max_len, config)
+}
 
-    let len = cmp::min(
-        cmp::max((config.name_length as i32) - (extension_len as i32), 0) as usize, // get a positive number
-        stem.len(),
-    );
+/// Shortens the name of a non-music file like a sidecar or the album cover, keeping its own
+/// extension, which isn't a music extension
+pub fn shorten_ordinary_name(name: &str, max_len: usize, config: &Config) -> String {
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let stem = name.strip_suffix(&extension).unwrap_or(name);
+    shorten_stem(stem, extension, max_len, config)
+}
+
+fn shorten_stem(stem: &str, extension: String, max_len: usize, config: &Config) -> String {
+    let unit = config.length_unit;
+    let max_len = max_len.saturating_sub(unit.length(&extension));
+    if unit.length(stem) <= max_len {
+        // trim to not have a blank before the extension
+        return format!("{}{}", stem.trim(), extension);
+    }
+
+    let ellipsis = if config.ellipsis && unit.length(ELLIPSIS) < max_len {
+        ELLIPSIS
+    } else {
+        ""
+    };
+    let max_len = max_len - unit.length(ellipsis);
+
+    // the longest prefix of whole grapheme clusters that fits
+    let mut len = 0;
+    let mut end = 0;
+    for (index, grapheme) in stem.grapheme_indices(true) {
+        len += unit.length(grapheme);
+        if len > max_len {
+            break;
+        }
+        end = index + grapheme.len();
+    }
+
+    // rather drop a partial word, unless it is the only one
+    let cuts_word = !stem[end..].starts_with(char::is_whitespace)
+        && !stem[..end].ends_with(char::is_whitespace);
+    if cuts_word {
+        if let Some(boundary) = stem[..end].rfind(char::is_whitespace) {
+            end = boundary;
+        }
+    }
 
-    // trim to not have a blank before the extension
-    format!("{}{}", &stem[..len].trim(), extension)
+    // a separator like " - " is useless at the end
+    let short_stem = stem[..end]
+        .trim()
+        .trim_end_matches(|c: char| c == '-' || c.is_whitespace());
+    if short_stem.is_empty() {
+        return extension;
+    }
+    format!("{}{}{}", short_stem, ellipsis, extension)
 }
 
 /// Returns the path's extension with leading dot (or the empty string)
@@ -105,7 +250,8 @@ pub fn get_extension(path: &Path) -> (String, usize) {
             ".{}",
             ext_without_dot.to_string_lossy().to_string().to_lowercase()
         );
-        if is_music_filename(&ext) {
+        // even excluded extensions are extensions, not part of the name
+        if is_music_filename(&ext, &Extensions::default()) {
             let len = ext.len();
             return (ext, len);
         }
@@ -115,7 +261,20 @@ pub fn get_extension(path: &Path) -> (String, usize) {
 
 /// Returns the file name's stem, i. e. the name without the extension given as second argument
 pub fn get_name_stem(name: &str, extension: &str) -> String {
-    name.replace(&extension, "")
+    name.replace(extension, "")
+}
+
+/// Moves a file or directory. Files are copied and removed if they cannot be renamed,
+/// e. g. because the new path is on another file system.
+pub fn move_file_or_directory(old_path: &Path, new_path: &Path) -> std::io::Result<()> {
+    if let Err(err) = fs::rename(old_path, new_path) {
+        if !old_path.is_file() {
+            return Err(err);
+        }
+        fs::copy(old_path, new_path)?;
+        fs::remove_file(old_path)?;
+    }
+    Ok(())
 }
 
 /// Returns a path made of the given string slice
@@ -126,77 +285,199 @@ pub fn string_to_path(file_name: &str) -> std::io::Result<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sanitize::SanitizeProfile;
 
     #[test]
     fn test_is_music_filename() {
-        assert!(is_music_filename("/tmp/music.mp3"));
-        assert!(!is_music_filename("/tmp/music.mp33"));
-        assert!(is_music_filename("/tmp/music.Mp3"));
-        assert!(is_music_filename("/tmp/music.FlAc"));
-        assert!(is_music_filename("/tmp/music.m4a"));
-        assert!(is_music_filename("/tmp/music.m4p"));
-        assert!(is_music_filename("/tmp/music.m4v"));
-        assert!(!is_music_filename("/tmp/music.mp4"));
+        let extensions = Extensions::default();
+        assert!(is_music_filename("/tmp/music.mp3", &extensions));
+        assert!(!is_music_filename("/tmp/music.mp33", &extensions));
+        assert!(is_music_filename("/tmp/music.Mp3", &extensions));
+        assert!(is_music_filename("/tmp/music.FlAc", &extensions));
+        assert!(is_music_filename("/tmp/music.m4a", &extensions));
+        assert!(is_music_filename("/tmp/music.m4p", &extensions));
+        assert!(is_music_filename("/tmp/music.m4v", &extensions));
+        assert!(!is_music_filename("/tmp/music.mp4", &extensions));
+        assert!(is_music_filename("/tmp/music.opus", &extensions));
+
+        let extensions = Extensions::new(["mp3", "flac"]);
+        assert!(is_music_filename("/tmp/music.mp3", &extensions));
+        assert!(!is_music_filename("/tmp/music.m4a", &extensions));
     }
 
     #[test]
     fn test_sanitize_file_or_directory_name() {
+        let sanitize = |name| sanitize_file_or_directory_name(name, &Config::default());
+        assert_eq!(sanitize("$foo $$ bar$"), "_foo __ bar_");
+
+        assert_eq!(sanitize("foo ??? bar"), "foo bar");
+
+        assert_eq!(sanitize("foo\\bar"), "foobar");
+
+        assert_eq!(sanitize("foo/bar"), "foo & bar");
+        assert_eq!(sanitize("foo/"), "foo &");
+
+        assert_eq!(sanitize("foo: bar"), "foo - bar");
+        assert_eq!(sanitize("foo:"), "foo -");
+
+        assert_eq!(sanitize("*foo * bar*"), "_foo _ bar_");
+        assert_eq!(sanitize("*foo ** bar*"), "_foo __ bar_");
+
+        assert_eq!(sanitize("foo ? bar"), "foo bar");
+        assert_eq!(sanitize("?foo bar?"), "foo bar");
+
+        assert_eq!(sanitize("\"foo bar\""), "foo bar");
+
+        assert_eq!(sanitize("<foo bar>"), "foo bar");
+
+        assert_eq!(sanitize("foo|bar"), "foo, bar");
+
+        // whitespace
+        assert_eq!(sanitize("foo\tbar"), "foo bar");
+        assert_eq!(sanitize("foo   bar"), "foo bar");
+        assert_eq!(sanitize("foo \t \t bar"), "foo bar");
+        assert_eq!(sanitize(" foo bar "), "foo bar");
+
+        // leading and trailing dots
+        assert_eq!(sanitize("...foo bar"), "foo bar");
+        assert_eq!(sanitize(".foo bar"), "foo bar");
+        assert_eq!(sanitize("foo bar..."), "foo bar");
+        assert_eq!(sanitize("foo bar."), "foo bar");
+
+        // example with french punctuation marks
         assert_eq!(
-            sanitize_file_or_directory_name("$foo $$ bar$"),
-            "_foo __ bar_"
+            sanitize("Où est le bien ? Où est le mal ?"),
+            "Où est le bien Où est le mal"
         );
+    }
 
+    #[test]
+    fn test_sanitize_with_replacements() {
         // special handling for "The Three ???"
+        let config = Config {
+            replacements: vec![(String::from("???"), String::from("Fragezeichen"))],
+            ..Config::default()
+        };
         assert_eq!(
-            sanitize_file_or_directory_name("foo ??? bar"),
+            sanitize_file_or_directory_name("foo ??? bar", &config),
             "foo Fragezeichen bar"
         );
+        assert_eq!(
+            sanitize_file_or_directory_name("foo ? bar", &config),
+            "foo bar"
+        );
+    }
 
-        assert_eq!(sanitize_file_or_directory_name("foo\\bar"), "foobar");
-
-        assert_eq!(sanitize_file_or_directory_name("foo/bar"), "foo & bar");
-        assert_eq!(sanitize_file_or_directory_name("foo/"), "foo &");
-
-        assert_eq!(sanitize_file_or_directory_name("foo: bar"), "foo - bar");
-        assert_eq!(sanitize_file_or_directory_name("foo:"), "foo -");
+    #[test]
+    fn test_sanitize_profiles() {
+        let config = |sanitize_profile| Config {
+            sanitize_profile,
+            ..Config::default()
+        };
+        let posix = config(SanitizeProfile::Posix);
+        assert_eq!(
+            sanitize_file_or_directory_name("foo: bar?...", &posix),
+            "foo: bar?..."
+        );
+        assert_eq!(sanitize_file_or_directory_name("AC/DC", &posix), "AC & DC");
+        assert_eq!(sanitize_file_or_directory_name("CON", &posix), "CON");
 
+        let windows = config(SanitizeProfile::Windows);
+        assert_eq!(sanitize_file_or_directory_name("CON", &windows), "CON_");
         assert_eq!(
-            sanitize_file_or_directory_name("*foo * bar*"),
-            "_foo _ bar_"
+            sanitize_file_or_directory_name("CON.Live", &windows),
+            "CON_.Live"
         );
         assert_eq!(
-            sanitize_file_or_directory_name("*foo ** bar*"),
-            "_foo __ bar_"
+            sanitize_file_or_directory_name("Aux .mp3", &windows),
+            "Aux_ .mp3"
         );
+        assert_eq!(sanitize_file_or_directory_name("com1", &windows), "com1_");
+        assert_eq!(
+            sanitize_file_or_directory_name("Console", &windows),
+            "Console"
+        );
+        assert_eq!(sanitize_file_or_directory_name("foo . .", &windows), "foo");
 
-        assert_eq!(sanitize_file_or_directory_name("foo ? bar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name("?foo bar?"), "foo bar");
-
-        assert_eq!(sanitize_file_or_directory_name("\"foo bar\""), "foo bar");
-
-        assert_eq!(sanitize_file_or_directory_name("<foo bar>"), "foo bar");
-
-        assert_eq!(sanitize_file_or_directory_name("foo|bar"), "foo, bar");
+        let strict_ascii = config(SanitizeProfile::StrictAscii);
+        assert_eq!(
+            sanitize_file_or_directory_name("Motörhead #1 @ home", &strict_ascii),
+            "Mot_rhead _1 _ home"
+        );
+    }
 
-        // whitespace
-        assert_eq!(sanitize_file_or_directory_name("foo\tbar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name("foo   bar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name("foo \t \t bar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name(" foo bar "), "foo bar");
+    #[test]
+    fn test_shorten_non_latin_names() {
+        let config = |name_length, length_unit| Config {
+            name_length,
+            length_unit,
+            ..Config::default()
+        };
+        let path = PathBuf::from("/foo/bar.mp3");
 
-        // leading and trailing dots
-        assert_eq!(sanitize_file_or_directory_name("...foo bar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name(".foo bar"), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name("foo bar..."), "foo bar");
-        assert_eq!(sanitize_file_or_directory_name("foo bar."), "foo bar");
+        assert_eq!(
+            shorten_names(&path, "Où est la plage.mp3", &config(10, LengthUnit::Chars)),
+            "Où est.mp3"
+        );
+        assert_eq!(
+            shorten_names(&path, "Où est la plage.mp3", &config(10, LengthUnit::Bytes)),
+            "Où.mp3"
+        );
+        // a character must not be cut in the middle of its bytes
+        assert_eq!(
+            shorten_names(&path, "東京の夜.mp3", &config(11, LengthUnit::Bytes)),
+            "東京.mp3"
+        );
+        assert_eq!(
+            shorten_names(&path, "東京の夜.mp3", &config(7, LengthUnit::Chars)),
+            "東京の.mp3"
+        );
+        // nor must an accent be separated from its letter
+        assert_eq!(
+            shorten_names(
+                &path,
+                "e\u{301}te\u{301}.mp3",
+                &config(6, LengthUnit::Chars)
+            ),
+            "e\u{301}.mp3"
+        );
+    }
 
-        // example with french punctuation marks
+    #[test]
+    fn test_shorten_names_with_ellipsis() {
+        let config = Config {
+            name_length: 12,
+            ellipsis: true,
+            ..Config::default()
+        };
         assert_eq!(
-            sanitize_file_or_directory_name("Où est le bien ? Où est le mal ?"),
-            "Où est le bien Où est le mal"
+            shorten_names(&PathBuf::from("/foo/bar.mp3"), "foo bar baz.mp3", &config),
+            "foo bar….mp3"
+        );
+        assert_eq!(
+            shorten_names(&PathBuf::from("/foo/bar.mp3"), "foo bar.mp3", &config),
+            "foo bar.mp3"
         );
     }
 
+    #[test]
+    fn test_unicode_normalization() {
+        let composed = "Caf\u{e9}";
+        let decomposed = "Cafe\u{301}";
+        assert_eq!(UnicodeNormalization::Nfc.apply(decomposed), composed);
+        assert_eq!(UnicodeNormalization::Nfd.apply(composed), decomposed);
+        assert_eq!(UnicodeNormalization::None.apply(decomposed), decomposed);
+
+        let old_path = PathBuf::from(format!("/music/{}.mp3", decomposed));
+        let new_path = PathBuf::from(format!("/music/{}.mp3", composed));
+        let config = Config {
+            unicode_normalization: UnicodeNormalization::Nfc,
+            ..Config::default()
+        };
+        assert!(is_same_path(&old_path, &new_path, &config));
+        assert!(!is_same_path(&old_path, &new_path, &Config::default()));
+    }
+
     #[test]
     fn test_shorten_names() {
         let config = Config {
@@ -225,7 +506,7 @@ mod tests {
                     ..Config::default()
                 },
             ),
-            "foo b.mp3"
+            "foo.mp3"
         );
         assert_eq!(
             shorten_names(